@@ -0,0 +1,118 @@
+//! Generates the built-in `ZType` and `ZBase` tables from `ztype.csv` and
+//! `zbase.csv`, so the spec tables are the only place they're written down.
+//!
+//! Each table becomes one invocation of the `ztypes!` or `zbases!` macro,
+//! which `ztype.rs` and `zbase.rs` expand into their enums and code maps.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// The rows after the header line of `file`, split into `n` trimmed fields.
+/// Only the last field may contain commas.
+fn rows(file: &str, n: usize) -> Vec<Vec<String>> {
+  println!("cargo:rerun-if-changed={}", file);
+  let text = fs::read_to_string(file)
+    .unwrap_or_else(|e| panic!("can't read {}: {}", file, e));
+  text
+    .lines()
+    .skip(1)
+    .filter(|line| !line.trim().is_empty())
+    .map(|line| {
+      let fields: Vec<String> =
+        line.splitn(n, ',').map(|x| x.trim().to_owned()).collect();
+      if fields.len() != n || fields.iter().any(|x| x.is_empty()) {
+        panic!("{}: expected {} fields in {:?}", file, n, line);
+      }
+      fields
+    })
+    .collect()
+}
+
+fn check_unique<'a>(file: &str, what: &str, xs: impl Iterator<Item = &'a str>) {
+  let mut seen = HashSet::new();
+  for x in xs {
+    if !seen.insert(x) {
+      panic!("{}: duplicate {} {:?}", file, what, x);
+    }
+  }
+}
+
+/// The categories a `ztype.csv` row may belong to.
+const ZTYPE_CATEGORIES: &[&str] = &["values"];
+
+fn ztypes() -> String {
+  let rows = rows("ztype.csv", 4);
+  check_unique("ztype.csv", "code", rows.iter().map(|r| r[0].as_str()));
+  check_unique("ztype.csv", "name", rows.iter().map(|r| r[1].as_str()));
+  let mut out = String::from("ztypes! {\n");
+  for row in rows {
+    let (code, name, category, description) =
+      (&row[0], &row[1], &row[2], &row[3]);
+    let code = code
+      .strip_prefix("0x")
+      .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+      .unwrap_or_else(|| panic!("ztype.csv: invalid code {:?}", code));
+    let valid_name = name.starts_with(|c: char| c.is_ascii_lowercase())
+      && name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit());
+    if !valid_name {
+      panic!("ztype.csv: invalid name {:?}", name);
+    }
+    if !ZTYPE_CATEGORIES.contains(&category.as_str()) {
+      panic!("ztype.csv: unknown category {:?} for {}", category, name);
+    }
+    let variant = name[..1].to_uppercase() + &name[1..];
+    out += &format!(
+      "  ({:#04x}, {}, {:?}, {:?}),\n",
+      code, variant, name, description
+    );
+  }
+  out + "}\n"
+}
+
+fn zbases() -> String {
+  let rows = rows("zbase.csv", 4);
+  check_unique("zbase.csv", "code", rows.iter().map(|r| r[0].as_str()));
+  check_unique("zbase.csv", "name", rows.iter().map(|r| r[1].as_str()));
+  let mut out = String::from("zbases! {\n");
+  for row in rows {
+    let (code, name, alphabet, description) =
+      (&row[0], &row[1], &row[2], &row[3]);
+    let code = match code.as_bytes() {
+      [b'\'', c, b'\''] if c.is_ascii_graphic() => *c as char,
+      _ => panic!("zbase.csv: invalid code {:?}", code),
+    };
+    let radix = name
+      .strip_prefix("base")
+      .map(|x| x.trim_end_matches(|c: char| c.is_ascii_lowercase()))
+      .filter(|x| x.parse::<usize>() == Ok(alphabet.len()))
+      .unwrap_or_else(|| {
+        panic!(
+          "zbase.csv: {:?} doesn't name a base {} alphabet",
+          name, alphabet
+        )
+      });
+    let mut digits = HashSet::new();
+    if !alphabet
+      .chars()
+      .all(|c| c.is_ascii_graphic() && digits.insert(c))
+    {
+      panic!("zbase.csv: invalid {} alphabet {:?}", name, alphabet);
+    }
+    out += &format!(
+      "  ({:?}, Z{}, {:?}, {:?}, {:?}),\n",
+      code, radix, name, alphabet, description
+    );
+  }
+  out + "}\n"
+}
+
+fn main() {
+  let out_dir = env::var("OUT_DIR").unwrap();
+  let out_dir = Path::new(&out_dir);
+  fs::write(out_dir.join("ztypes.rs"), ztypes()).unwrap();
+  fs::write(out_dir.join("zbases.rs"), zbases()).unwrap();
+}
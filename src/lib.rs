@@ -8,26 +8,49 @@ extern crate rand;
 
 extern crate nom;
 
+pub mod limits;
 pub mod zbase;
+pub mod zchunk;
+pub mod zconv;
+pub mod zdag;
+pub mod zgc;
+pub mod zlink;
+pub mod zmerkle;
+pub mod znum;
+pub mod zreader;
+pub mod zref;
+pub mod zserde;
+pub mod zsign;
+pub mod zstore;
+pub mod zsync;
 pub mod ztype;
+pub mod zvalid;
 
-use nom::branch::alt;
 use nom::bytes::complete::{tag, take};
 use nom::character::complete::multispace1;
-use nom::combinator::map;
 use nom::error::ErrorKind;
 use nom::error::ParseError;
-use nom::multi::{count, separated_list0};
-use nom::sequence::{delimited, terminated};
+use nom::sequence::terminated;
 use nom::Err;
 use nom::IResult;
 use nom::InputLength;
 
+use limits::DecodeLimit;
 use std::fmt;
+use std::io;
+use std::io::Write;
 use zbase::ZBase;
 use zbase::ZBaseError;
 use ztype::ZType;
 use ztype::ZTypeError;
+use ztype::ZTypeRegistry;
+use zvalid::Invalid;
+
+pub use limits::DecodeLimits;
+pub use zlink::ZLink;
+pub use zreader::ZExprReader;
+pub use zref::{ZConsRef, ZExprRef};
+pub use zserde::{from_slice, from_str, from_zexpr, to_vec, to_zexpr};
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub enum ZExpr {
@@ -37,24 +60,74 @@ pub enum ZExpr {
 
 impl fmt::Display for ZExpr {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    write!(
-      f,
-      "{}",
-      match self {
-        Self::Atom(ty, at) => {
-          format!("{}:{}", zbase::encode(ZBase::Z32, at), ty)
+    self.display_with(&ZTypeRegistry::new()).fmt(f)
+  }
+}
+
+/// A `ZExpr` printed with the names of the extension types in a registry.
+/// See `ZExpr::display_with`.
+pub struct ZExprDisplay<'a> {
+  expr: &'a ZExpr,
+  registry: &'a ZTypeRegistry,
+}
+
+impl<'a> fmt::Display for ZExprDisplay<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    // the remaining elements of each cons being printed, and whether any have
+    // been printed yet
+    let mut stack: Vec<(std::slice::Iter<ZExpr>, bool)> = vec![];
+    let mut x = self.expr;
+    loop {
+      match x {
+        ZExpr::Atom(ty, at) => match ZLink::from_zexpr(x) {
+          Some(link) => write!(f, "{}", link)?,
+          None => write!(
+            f,
+            "{}:{}",
+            zbase::encode(ZBase::Z32, at),
+            self.registry.display(*ty)
+          )?,
+        },
+        ZExpr::Cons(xs) => {
+          write!(f, "(")?;
+          stack.push((xs.iter(), false));
         }
-        Self::Cons(xs) => {
-          format!(
-            "({})",
-            xs.iter()
-              .map(|x| format!("{}", x))
-              .collect::<Vec<String>>()
-              .join(" ")
-          )
+      }
+      x = loop {
+        let (xs, started) = match stack.last_mut() {
+          Some(top) => top,
+          None => return Ok(()),
+        };
+        match xs.next() {
+          Some(y) => {
+            if *started {
+              write!(f, " ")?;
+            }
+            *started = true;
+            break y;
+          }
+          None => {
+            write!(f, ")")?;
+            stack.pop();
+          }
+        }
+      };
+    }
+  }
+}
+
+/// Conses are taken apart onto a heap stack, so that dropping a deeply nested
+/// expression doesn't recurse once per level.
+impl Drop for ZExpr {
+  fn drop(&mut self) {
+    if let Self::Cons(xs) = self {
+      let mut stack = std::mem::take(xs);
+      while let Some(mut x) = stack.pop() {
+        if let Self::Cons(ys) = &mut x {
+          stack.append(ys);
         }
       }
-    )
+    }
   }
 }
 
@@ -69,109 +142,333 @@ impl fmt::Display for ZExpr {
 
 impl ZExpr {
   pub fn serialize(&self) -> Vec<u8> {
-    match self {
-      Self::Atom(typ, dat) => {
-        let typ_len: u8 = typ.serialize().len() as u8;
-        let dat_len: u64 = dat.len() as u64;
-        let dat_len_len: u8 = number_of_bytes(dat_len);
-
-        //println!("ser dat_len {}", dat_len);
-        let dat_len: Vec<u8> = dat_len.to_le_bytes()[0..(dat_len_len as usize)]
-          .to_vec()
-          .into_iter()
-          .rev()
-          .collect();
-        //println!("ser dat_len {:?}", dat_len);
-
-        //println!("ser typ_len {}", typ_len);
-        //println!("ser dat_len_len {}", dat_len_len);
-        let size_byte: u8 = if typ.is_some_len() {
-          (0b0111_1111) & (1 << 6 | (typ_len - 1) << 3) | (dat_len_len - 1)
-        } else {
-          (0b0011_1111) & ((typ_len - 1) << 3) | (dat_len_len - 1)
-        };
-
-        let mut ret = vec![];
+    let mut ret = Vec::with_capacity(self.encoded_len());
+    self
+      .serialize_into(&mut ret)
+      .expect("writing to a Vec cannot fail");
+    ret
+  }
 
-        ret.extend(vec![size_byte]);
-        ret.extend(typ.serialize());
-        ret.extend(dat_len);
-        ret.extend(dat);
-        //println!("ser ret {:?}", ret);
-        ret
+  /// The number of bytes `serialize` produces for this expression.
+  pub fn encoded_len(&self) -> usize {
+    let mut len = 0;
+    let mut stack = vec![self];
+    while let Some(x) = stack.pop() {
+      match x {
+        Self::Atom(typ, dat) => {
+          len += ZHeader::Atom(*typ, dat.len() as u64).encoded_len() + dat.len()
+        }
+        Self::Cons(xs) => {
+          len += ZHeader::Cons(xs.len() as u64).encoded_len();
+          stack.extend(xs.iter());
+        }
       }
-      Self::Cons(xs) => {
-        let xs_len: u64 = xs.len() as u64;
-        let xs_len_len: u8 = number_of_bytes(xs_len);
-        let xs_len: Vec<u8> = xs_len.to_le_bytes()[0..(xs_len_len as usize)]
-          .to_vec()
-          .into_iter()
-          .rev()
-          .collect();
-        let size_byte: u8 = 0b1000_0111 & (0b1000_0000 | (xs_len_len - 1));
-        let mut ret: Vec<u8> = vec![];
-        ret.extend(vec![size_byte]);
-        ret.extend(xs_len);
-        ret.extend(xs.iter().fold(vec![], |mut acc, x| {
-          acc.extend(ZExpr::serialize(x));
-          acc
-        }));
-        ret
+    }
+    len
+  }
+
+  /// Write the bytes of `serialize` to `w` in a single pass, without building
+  /// any intermediate buffers.
+  pub fn serialize_into<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+    // the remaining elements of each cons being written
+    let mut stack: Vec<std::slice::Iter<ZExpr>> = vec![];
+    let mut next = Some(self);
+    while let Some(x) = next {
+      match x {
+        Self::Atom(typ, dat) => {
+          ZHeader::Atom(*typ, dat.len() as u64).serialize_into(w)?;
+          w.write_all(dat)?;
+        }
+        Self::Cons(xs) => {
+          ZHeader::Cons(xs.len() as u64).serialize_into(w)?;
+          stack.push(xs.iter());
+        }
       }
+      next = loop {
+        match stack.last_mut() {
+          None => break None,
+          Some(xs) => match xs.next() {
+            Some(y) => break Some(y),
+            None => {
+              stack.pop();
+            }
+          },
+        }
+      };
     }
+    Ok(())
+  }
+
+  /// Display with the extension types in `registry` named, so that
+  /// `parse_registered` can read it back.
+  pub fn display_with<'a>(
+    &'a self,
+    registry: &'a ZTypeRegistry,
+  ) -> ZExprDisplay<'a> {
+    ZExprDisplay {
+      expr: self,
+      registry,
+    }
+  }
+
+  /// A `link` atom holding the blake3 digest of this expression's
+  /// serialization. See `ZLink`, and `ZLink::of` for when this fails.
+  pub fn link(&self) -> Result<ZExpr, Invalid> {
+    ZLink::of(self).map(ZExpr::from)
   }
 
   pub fn deserialize(
     i: &[u8],
   ) -> IResult<&[u8], ZExpr, ZExprDeserialError<&[u8]>> {
-    //println!("de inp {:?}", i);
-    let (i, size) = take(1 as usize)(i)?;
-    let (is_atom, len_typ, typ_len, dat_len_len) = (
-      ((size[0] & 0b1000_0000) >> 7) == 0,
-      ((size[0] & 0b0100_0000) >> 6) == 1,
-      ((size[0] & 0b0011_1000) >> 3) + 1,
-      (size[0] & 0b111) + 1,
-    );
-    //println!("de is_atom {}", is_atom);
-    //println!("de len_typ {}", len_typ);
-    //println!("de typ_len {}", typ_len);
-    //println!("de dat_len_len {}", dat_len_len);
+    ZExpr::deserialize_with_limits(i, &DecodeLimits::unlimited())
+  }
+
+  /// Like `deserialize`, but fails with `LimitExceeded` as soon as a header
+  /// breaks one of `limits`, before anything is allocated for it.
+  pub fn deserialize_with_limits<'a>(
+    i: &'a [u8],
+    limits: &DecodeLimits,
+  ) -> IResult<&'a [u8], ZExpr, ZExprDeserialError<&'a [u8]>> {
+    ZExpr::deserialize_limited(i, limits, false, None)
+  }
+
+  /// Like `deserialize`, but fails with `Invalid` at the first atom whose data
+  /// breaks its type. See `ZExpr::validate`.
+  pub fn deserialize_validated(
+    i: &[u8],
+  ) -> IResult<&[u8], ZExpr, ZExprDeserialError<&[u8]>> {
+    ZExpr::deserialize_registered(i, &ZTypeRegistry::new())
+  }
+
+  /// Like `deserialize_validated`, but with the extension types in `registry`
+  /// checked by their validators instead of failing as `Unregistered`.
+  pub fn deserialize_registered<'a>(
+    i: &'a [u8],
+    registry: &ZTypeRegistry,
+  ) -> IResult<&'a [u8], ZExpr, ZExprDeserialError<&'a [u8]>> {
+    ZExpr::deserialize_limited(
+      i,
+      &DecodeLimits::unlimited(),
+      false,
+      Some(registry),
+    )
+  }
+
+  /// Like `deserialize`, but only accepts the exact bytes `serialize` would
+  /// write for the expression, failing with `NonCanonical` at the first header
+  /// encoded any other way. An expression whose sized atoms all hold as many
+  /// bytes as their types say has exactly one canonical form, so hashes of
+  /// canonical bytes can serve as identifiers for such expressions. Any other
+  /// sized atom is written as if its type had the length of its data.
+  pub fn deserialize_canonical(
+    i: &[u8],
+  ) -> IResult<&[u8], ZExpr, ZExprDeserialError<&[u8]>> {
+    ZExpr::deserialize_limited(i, &DecodeLimits::unlimited(), true, None)
+  }
+
+  fn deserialize_limited<'a>(
+    i: &'a [u8],
+    limits: &DecodeLimits,
+    canonical: bool,
+    validate: Option<&ZTypeRegistry>,
+  ) -> IResult<&'a [u8], ZExpr, ZExprDeserialError<&'a [u8]>> {
+    let start = i.len();
+    // the conses still being filled, with the number of elements they lack
+    let mut stack: Vec<(Vec<ZExpr>, u64)> = vec![];
+    let mut i = i;
+    loop {
+      let input = i;
+      let exceeded =
+        |e| Err::Error(ZExprDeserialError::LimitExceeded(input, e));
+      let (rest, header) = if canonical {
+        ZHeader::deserialize_canonical(i)?
+      } else {
+        ZHeader::deserialize(i)?
+      };
+      let consumed = (start - rest.len()) as u64;
+      let mut x = match header {
+        ZHeader::Atom(typ, dat_len) => {
+          limits.check_atom_len(dat_len).map_err(exceeded)?;
+          limits
+            .check_total(consumed.saturating_add(dat_len))
+            .map_err(exceeded)?;
+          let (rest, dat) = take(dat_len)(rest)?;
+          if let Some(registry) = validate {
+            registry.check_atom(typ, dat).map_err(|violation| {
+              let path = stack.iter().map(|(xs, _)| xs.len()).collect();
+              Err::Error(ZExprDeserialError::Invalid(
+                input,
+                Invalid { path, violation },
+              ))
+            })?;
+          }
+          i = rest;
+          ZExpr::Atom(typ, dat.to_owned())
+        }
+        ZHeader::Cons(xs_len) => {
+          limits.check_cons_len(xs_len).map_err(exceeded)?;
+          limits.check_depth(stack.len() + 1).map_err(exceeded)?;
+          limits.check_total(consumed).map_err(exceeded)?;
+          i = rest;
+          if xs_len > 0 {
+            // every element takes at least two bytes, so a length larger
+            // than that can't be trusted for preallocation
+            let xs = Vec::with_capacity((xs_len as usize).min(i.len() / 2));
+            stack.push((xs, xs_len));
+            continue;
+          }
+          ZExpr::Cons(vec![])
+        }
+      };
+      // hand the finished expression up to its parents
+      loop {
+        match stack.last_mut() {
+          None => return Ok((i, x)),
+          Some((xs, remaining)) => {
+            xs.push(x);
+            *remaining -= 1;
+            if *remaining > 0 {
+              break;
+            }
+          }
+        }
+        let (xs, _) = stack.pop().unwrap();
+        x = ZExpr::Cons(xs);
+      }
+    }
+  }
+}
+
+/// The size byte, type code and length prefix which begin every serialized
+/// `ZExpr`. For an atom this holds its type and the length of its data, for a
+/// cons the number of its elements.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ZHeader {
+  Atom(ZType, u64),
+  Cons(u64),
+}
+
+impl ZHeader {
+  pub fn deserialize(
+    i: &[u8],
+  ) -> IResult<&[u8], ZHeader, ZExprDeserialError<&[u8]>> {
+    let (i, size) = take(1usize)(i)?;
+    let (is_atom, len_typ, typ_len, dat_len_len) = Self::size_fields(size[0]);
     if is_atom {
       let (i_type, typ_code) = take(typ_len)(i)?;
       let (i, dat_len) = take(dat_len_len)(i_type)?;
       let dat_len = dat_len.iter().fold(0, |acc, &x| (acc * 256) + x as u64);
       let len = if len_typ { Some(dat_len) } else { None };
-      let (_, typ) = match ZType::deserialize(typ_code, len) {
-        Some(x) => Ok((i, x)),
+      match ZType::deserialize(typ_code, len) {
+        Some(typ) => Ok((i, ZHeader::Atom(typ, dat_len))),
         None => Err(Err::Error(ZExprDeserialError::InvalidZTypeCode(
           i_type,
           typ_code.to_owned(),
         ))),
-      }?;
-      let (i, dat) = take(dat_len)(i)?;
-      Ok((i, ZExpr::Atom(typ, dat.to_owned())))
+      }
     } else {
-      println!("de is_atom {}", is_atom);
       let (i, xs_len) = take(dat_len_len)(i)?;
       let xs_len = xs_len.iter().fold(0, |acc, &x| (acc * 256) + x as u64);
-      let (i, xs) = count(ZExpr::deserialize, xs_len as usize)(i)?;
-      Ok((i, ZExpr::Cons(xs)))
+      Ok((i, ZHeader::Cons(xs_len)))
+    }
+  }
+
+  /// Like `deserialize`, but fails with `NonCanonical` unless the header is
+  /// exactly what `serialize_into` would write for it.
+  pub fn deserialize_canonical(
+    i: &[u8],
+  ) -> IResult<&[u8], ZHeader, ZExprDeserialError<&[u8]>> {
+    let (rest, header) = ZHeader::deserialize(i)?;
+    let size = i[0];
+    let (_, _, _, len_len) = Self::size_fields(size);
+    let non_canonical =
+      |e| Err(Err::Error(ZExprDeserialError::NonCanonical(i, e)));
+    let len = match header {
+      // `ZType::deserialize` already rejects type codes with leading zeros
+      ZHeader::Atom(_, dat_len) => dat_len,
+      ZHeader::Cons(xs_len) => {
+        if size & 0b0111_1000 != 0 {
+          return non_canonical(NonCanonical::ConsSizeByte(size));
+        }
+        xs_len
+      }
+    };
+    if len_len != number_of_bytes(len) {
+      return non_canonical(NonCanonical::LengthPrefix(len_len));
+    }
+    Ok((rest, header))
+  }
+
+  /// The number of bytes `serialize_into` writes.
+  pub fn encoded_len(&self) -> usize {
+    match self {
+      Self::Atom(typ, dat_len) => {
+        1 + typ.code_len() + number_of_bytes(*dat_len) as usize
+      }
+      Self::Cons(xs_len) => 1 + number_of_bytes(*xs_len) as usize,
     }
   }
+
+  /// Write the size byte, type code and big-endian length prefix.
+  pub fn serialize_into<W: Write + ?Sized>(&self, w: &mut W) -> io::Result<()> {
+    let mut buf = [0u8; 17];
+    let (size_byte, typ_len, len) = match self {
+      Self::Atom(typ, dat_len) => {
+        let typ_len = typ.code_len() as u8;
+        let code = typ.code().to_be_bytes();
+        buf[1..1 + typ_len as usize]
+          .copy_from_slice(&code[8 - typ_len as usize..]);
+        let dat_len_len = number_of_bytes(*dat_len);
+        let size_byte: u8 = if typ.is_some_len() {
+          (0b0111_1111) & (1 << 6 | (typ_len - 1) << 3) | (dat_len_len - 1)
+        } else {
+          (0b0011_1111) & ((typ_len - 1) << 3) | (dat_len_len - 1)
+        };
+        (size_byte, typ_len as usize, *dat_len)
+      }
+      Self::Cons(xs_len) => {
+        let xs_len_len = number_of_bytes(*xs_len);
+        let size_byte: u8 = 0b1000_0111 & (0b1000_0000 | (xs_len_len - 1));
+        (size_byte, 0, *xs_len)
+      }
+    };
+    let len_len = number_of_bytes(len) as usize;
+    buf[0] = size_byte;
+    let n = 1 + typ_len;
+    buf[n..n + len_len].copy_from_slice(&len.to_be_bytes()[8 - len_len..]);
+    w.write_all(&buf[..n + len_len])
+  }
+
+  /// Split a size byte into (is_atom, is_sized, typ_len, dat_len_len).
+  pub(crate) fn size_fields(size: u8) -> (bool, bool, u8, u8) {
+    (
+      ((size & 0b1000_0000) >> 7) == 0,
+      ((size & 0b0100_0000) >> 6) == 1,
+      ((size & 0b0011_1000) >> 3) + 1,
+      (size & 0b111) + 1,
+    )
+  }
 }
 
 pub fn number_of_bytes(x: u64) -> u8 {
-  let mut n: u32 = 1;
-  let base: u64 = 256;
-  while base.pow(n) <= x {
-    n += 1;
-  }
-  return n as u8;
+  (8 - (x.leading_zeros() / 8) as u8).max(1)
+}
+
+/// A header part encoded differently from how `serialize` writes it.
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum NonCanonical {
+  /// A length prefix with leading zero bytes: (prefix length)
+  LengthPrefix(u8),
+  /// A cons size byte with the sized or type length bits set: (size byte)
+  ConsSizeByte(u8),
 }
 
 #[derive(PartialEq, Eq, Debug, Clone)]
 pub enum ZExprDeserialError<I> {
   InvalidZTypeCode(I, Vec<u8>),
+  LimitExceeded(I, DecodeLimit),
+  NonCanonical(I, NonCanonical),
+  Invalid(I, Invalid),
   NomErr(I, ErrorKind),
 }
 
@@ -179,6 +476,9 @@ impl<I> ZExprDeserialError<I> {
   pub fn rest(self) -> I {
     match self {
       Self::InvalidZTypeCode(i, _) => i,
+      Self::LimitExceeded(i, _) => i,
+      Self::NonCanonical(i, _) => i,
+      Self::Invalid(i, _) => i,
       Self::NomErr(i, _) => i,
     }
   }
@@ -214,6 +514,9 @@ where
 pub enum ZExprError<I> {
   ZTypeErr(I, ZTypeError<I>),
   ZBaseErr(I, ZBaseError<I>),
+  /// A link whose digest doesn't fit its algorithm: (digest length)
+  LinkLength(I, usize),
+  Invalid(I, Invalid),
   NomErr(I, ErrorKind),
 }
 
@@ -222,6 +525,8 @@ impl<I> ZExprError<I> {
     match self {
       Self::ZTypeErr(i, _) => i,
       Self::ZBaseErr(i, _) => i,
+      Self::LinkLength(i, _) => i,
+      Self::Invalid(i, _) => i,
       Self::NomErr(i, _) => i,
     }
   }
@@ -265,27 +570,102 @@ impl<I: Clone> From<ZTypeError<I>> for ZExprError<I> {
   }
 }
 
-// <bytes>:<type>
+// <bytes>:<type> or #<bytes>
 pub fn parse_atom(i: &str) -> IResult<&str, ZExpr, ZExprError<&str>> {
+  parse_atom_with(i, &ZTypeRegistry::new())
+}
+
+fn parse_atom_with<'a>(
+  i: &'a str,
+  registry: &ZTypeRegistry,
+) -> IResult<&'a str, ZExpr, ZExprError<&'a str>> {
+  if i.starts_with('#') {
+    let (i, link) = zlink::parse(i)?;
+    return Ok((i, link.into()));
+  }
   let (i, (_, at)) =
-    terminated(zbase::parse, tag(":"))(i).map_err(|e| Err::convert(e))?;
-  let (i, ty) = ztype::parse(i).map_err(|e| Err::convert(e))?;
+    terminated(zbase::parse, tag(":"))(i).map_err(Err::convert)?;
+  let (i, ty) = registry.parse(i).map_err(Err::convert)?;
   Ok((i, ZExpr::Atom(ty, at)))
 }
 
 pub fn parse(i: &str) -> IResult<&str, ZExpr, ZExprError<&str>> {
-  alt((
-    parse_atom,
-    map(
-      delimited(tag("("), separated_list0(multispace1, parse), tag(")")),
-      ZExpr::Cons,
-    ),
-  ))(i)
+  parse_with(i, &ZTypeRegistry::new(), false)
+}
+
+/// Like `parse`, but fails with `Invalid` at the first atom whose data breaks
+/// its type. See `ZExpr::validate`.
+pub fn parse_validated(i: &str) -> IResult<&str, ZExpr, ZExprError<&str>> {
+  parse_with(i, &ZTypeRegistry::new(), true)
+}
+
+/// Like `parse_validated`, but also accepts the names of the extension types
+/// in `registry`, and checks their atoms with its validators.
+pub fn parse_registered<'a>(
+  i: &'a str,
+  registry: &ZTypeRegistry,
+) -> IResult<&'a str, ZExpr, ZExprError<&'a str>> {
+  parse_with(i, registry, true)
+}
+
+fn parse_with<'a>(
+  i: &'a str,
+  registry: &ZTypeRegistry,
+  validate: bool,
+) -> IResult<&'a str, ZExpr, ZExprError<&'a str>> {
+  // the elements parsed so far of each open cons
+  let mut stack: Vec<Vec<ZExpr>> = vec![];
+  let mut i = i;
+  loop {
+    let mut x = match i.strip_prefix('(') {
+      Some(rest) => {
+        i = rest;
+        stack.push(vec![]);
+        match i.strip_prefix(')') {
+          Some(rest) => {
+            i = rest;
+            ZExpr::Cons(stack.pop().unwrap())
+          }
+          None => continue,
+        }
+      }
+      None => {
+        let (rest, x) = parse_atom_with(i, registry)?;
+        if let (true, ZExpr::Atom(typ, dat)) = (validate, &x) {
+          registry.check_atom(*typ, dat).map_err(|violation| {
+            let path = stack.iter().map(|xs| xs.len()).collect();
+            Err::Error(ZExprError::Invalid(i, Invalid { path, violation }))
+          })?;
+        }
+        i = rest;
+        x
+      }
+    };
+    // close every cons which ends after `x`
+    loop {
+      match stack.last_mut() {
+        None => return Ok((i, x)),
+        Some(xs) => {
+          xs.push(x);
+          match i.strip_prefix(')') {
+            Some(rest) => i = rest,
+            None => {
+              let (rest, _) = multispace1(i)?;
+              i = rest;
+              break;
+            }
+          }
+        }
+      }
+      x = ZExpr::Cons(stack.pop().unwrap());
+    }
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::ztype::ExtCode;
   use crate::ztype::ZType::*;
   use crate::ZExpr;
   use quickcheck::{Arbitrary, Gen, StdThreadGen};
@@ -305,6 +685,8 @@ mod tests {
   }
 
   #[test]
+  // predates the lint and is kept as it was written
+  #[allow(clippy::useless_vec)]
   fn zexpr_print() {
     let a = ZExpr::Atom(Bytes(None), vec![0]);
     assert_eq!(format!("{}", a), "vy:bytes");
@@ -341,6 +723,220 @@ mod tests {
       _ => false,
     }
   }
+  #[test]
+  fn zexpr_deserialize_limits() {
+    let limits = DecodeLimits {
+      max_depth: 2,
+      max_atom_len: 4,
+      max_cons_len: 3,
+      max_total_bytes: 32,
+    };
+    let atom = |n| ZExpr::Atom(Bytes(None), vec![0; n]);
+    let ok = ZExpr::Cons(vec![ZExpr::Cons(vec![atom(4)]), atom(0), atom(1)]);
+    assert_eq!(
+      ZExpr::deserialize_with_limits(&ok.serialize(), &limits),
+      Ok((b"".as_ref(), ok))
+    );
+    let too_long = |x: ZExpr, limit| {
+      let bytes = x.serialize();
+      match ZExpr::deserialize_with_limits(&bytes, &limits) {
+        Err(Err::Error(ZExprDeserialError::LimitExceeded(_, l))) => l == limit,
+        _ => false,
+      }
+    };
+    assert!(too_long(atom(5), DecodeLimit::AtomLength(5)));
+    assert!(too_long(
+      ZExpr::Cons(vec![atom(0), atom(0), atom(0), atom(0)]),
+      DecodeLimit::ConsArity(4)
+    ));
+    assert!(too_long(
+      ZExpr::Cons(vec![ZExpr::Cons(vec![ZExpr::Cons(vec![])])]),
+      DecodeLimit::Depth(3)
+    ));
+    assert!(too_long(
+      ZExpr::Cons(vec![ZExpr::Cons(vec![atom(4), atom(4), atom(4)]); 2]),
+      DecodeLimit::TotalBytes(34)
+    ));
+    // a cons claiming 2^56 elements is refused before anything is reserved
+    let hostile = [0b1000_0111, 1, 0, 0, 0, 0, 0, 0, 0];
+    assert_eq!(
+      ZExpr::deserialize_with_limits(&hostile, &DecodeLimits::default()),
+      Err(Err::Error(ZExprDeserialError::LimitExceeded(
+        &hostile[..],
+        DecodeLimit::ConsArity(1 << 56)
+      )))
+    );
+    assert!(ZExpr::deserialize(&hostile).is_err());
+  }
+
+  #[quickcheck]
+  fn zexpr_encoded_len(x: ZExpr) -> bool {
+    let mut out = vec![];
+    x.serialize_into(&mut out).unwrap();
+    x.encoded_len() == out.len() && out == x.serialize()
+  }
+
+  #[test]
+  fn zexpr_deserialize_canonical() {
+    let x = ZExpr::Cons(vec![ZExpr::Atom(Nat(None), vec![1, 2])]);
+    let bytes = x.serialize();
+    assert_eq!(
+      ZExpr::deserialize_canonical(&bytes),
+      Ok((&[][..], x.clone()))
+    );
+    let non_canonical = |bytes: &[u8]| match ZExpr::deserialize_canonical(bytes)
+    {
+      Err(Err::Error(ZExprDeserialError::NonCanonical(i, e))) => {
+        (bytes.len() - i.len(), e)
+      }
+      y => panic!("unexpected {:?}", y),
+    };
+    // a cons length of 1 written in two bytes
+    let cons = [&[0b1000_0001, 0, 1][..], &bytes[2..]].concat();
+    assert_eq!(ZExpr::deserialize(&cons), Ok((&[][..], x.clone())));
+    assert_eq!(non_canonical(&cons), (0, NonCanonical::LengthPrefix(2)));
+    // a cons with the sized bit set
+    let sized = [&[0b1100_0000][..], &bytes[1..]].concat();
+    assert_eq!(ZExpr::deserialize(&sized), Ok((&[][..], x.clone())));
+    assert_eq!(
+      non_canonical(&sized),
+      (0, NonCanonical::ConsSizeByte(0b1100_0000))
+    );
+    // an atom length of 2 written in three bytes
+    let atom = [&bytes[..2], &[0b0000_0010, 0x02, 0, 0, 2, 1, 2][..]].concat();
+    assert_eq!(ZExpr::deserialize(&atom), Ok((&[][..], x)));
+    assert_eq!(non_canonical(&atom), (2, NonCanonical::LengthPrefix(3)));
+    // a type code with a leading zero byte isn't a code at all
+    let code = [&bytes[..2], &[0b0000_1000, 0, 0x02, 2, 1, 2][..]].concat();
+    match ZExpr::deserialize(&code) {
+      Err(Err::Error(ZExprDeserialError::InvalidZTypeCode(_, c))) => {
+        assert_eq!(c, vec![0, 0x02])
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+    // the length prefix of an empty atom is a single zero byte
+    let empty = ZExpr::Atom(Bytes(None), vec![]).serialize();
+    assert_eq!(empty, vec![0, 0, 0]);
+    assert!(ZExpr::deserialize_canonical(&empty).is_ok());
+  }
+
+  #[quickcheck]
+  fn zexpr_canonical_roundtrip(x: ZExpr) -> bool {
+    match ZExpr::deserialize_canonical(&x.serialize()) {
+      Ok((rest, y)) => rest.is_empty() && x == y,
+      _ => false,
+    }
+  }
+
+  #[test]
+  fn zexpr_deep_nesting() {
+    // deep enough to overflow the stack if any of these recursed; compared by
+    // their bytes, since the derived impls do recurse
+    let depth = 100_000;
+    let mut x = ZExpr::Atom(Nat(Some(1)), vec![7]);
+    for _ in 0..depth {
+      x = ZExpr::Cons(vec![x]);
+    }
+    let bytes = x.serialize();
+    assert_eq!(bytes.len(), x.encoded_len());
+    let (rest, y) = ZExpr::deserialize(&bytes).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(y.serialize(), bytes);
+    let (_, r) = ZExprRef::deserialize(&bytes).unwrap();
+    assert_eq!(r.to_zexpr().serialize(), bytes);
+    let text = format!("{}", x);
+    assert_eq!(text.len(), 2 * depth + "ah:nat1".len());
+    let (rest, z) = parse(&text).unwrap();
+    assert!(rest.is_empty());
+    assert_eq!(z.serialize(), bytes);
+    let limits = DecodeLimits {
+      max_depth: depth - 1,
+      ..DecodeLimits::unlimited()
+    };
+    match ZExpr::deserialize_with_limits(&bytes, &limits) {
+      Err(Err::Error(ZExprDeserialError::LimitExceeded(_, e))) => {
+        assert_eq!(e, DecodeLimit::Depth(depth))
+      }
+      _ => panic!("expected the depth limit to be hit"),
+    }
+  }
+
+  #[test]
+  fn zexpr_validated() {
+    use crate::zvalid::Violation;
+    // the plain readers take atoms as they are
+    let x = ZExpr::Cons(vec![
+      ZExpr::Atom(Text(None), b"ok".to_vec()),
+      ZExpr::Cons(vec![ZExpr::Atom(Text(None), b"\xff".to_vec())]),
+    ]);
+    let bytes = x.serialize();
+    assert_eq!(ZExpr::deserialize(&bytes), Ok((&[][..], x)));
+    match ZExpr::deserialize_validated(&bytes) {
+      Err(Err::Error(ZExprDeserialError::Invalid(i, e))) => {
+        assert_eq!(bytes.len() - i.len(), 9);
+        assert_eq!(e.path, vec![1, 0]);
+        assert_eq!(e.violation, Violation::Utf8(0));
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+    // serialized sized atoms take their length from their data, but text can
+    // declare another
+    let y = ZExpr::Cons(vec![ZExpr::Atom(Int(Some(8)), vec![1])]);
+    let text = format!("{}", y);
+    assert_eq!(parse(&text), Ok(("", y)));
+    match parse_validated(&text) {
+      Err(Err::Error(ZExprError::Invalid(i, e))) => {
+        assert_eq!(i, &text[1..]);
+        assert_eq!(e.path, vec![0]);
+        assert_eq!(e.violation, Violation::Length(8, 1));
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+    let z = ZExpr::Atom(Char(Some(4)), vec![0, 0, 0, 0x61]);
+    assert_eq!(ZExpr::deserialize_validated(&z.serialize()).unwrap().1, z);
+    assert_eq!(parse_validated(&format!("{}", z)), Ok(("", z)));
+  }
+
+  #[test]
+  fn zexpr_registered() {
+    let mut reg = ZTypeRegistry::new();
+    reg
+      .register(0x1f00, "point", Some(|dat| dat.len() == 2))
+      .unwrap();
+    let x = ZExpr::Cons(vec![
+      ZExpr::Atom(Nat(None), vec![1]),
+      ZExpr::Atom(Ext(ExtCode::new(0x1f00).unwrap(), None), vec![3, 4]),
+    ]);
+    let bytes = x.serialize();
+    assert_eq!(bytes, vec![128, 2, 0, 2, 1, 1, 8, 0x1f, 0, 2, 3, 4]);
+    assert_eq!(x.encoded_len(), bytes.len());
+    assert_eq!(
+      ZExpr::deserialize_canonical(&bytes),
+      Ok((&[][..], x.clone()))
+    );
+    assert_eq!(ZExpr::deserialize_registered(&bytes, &reg).unwrap().1, x);
+    match ZExpr::deserialize_validated(&bytes) {
+      Err(Err::Error(ZExprDeserialError::Invalid(_, e))) => {
+        assert_eq!(e.path, vec![1]);
+        assert_eq!(e.violation, zvalid::Violation::Unregistered(0x1f00));
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+
+    assert_eq!(format!("{}", x), "(vb:nat var:ext1f00)");
+    let text = format!("{}", x.display_with(&reg));
+    assert_eq!(text, "(vb:nat var:point)");
+    assert_eq!(parse_registered(&text, &reg), Ok(("", x.clone())));
+    assert!(parse(&text).is_err());
+    assert_eq!(parse("(vb:nat var:ext1f00)"), Ok(("", x)));
+    match parse_registered("(vb:point)", &reg) {
+      Err(Err::Error(ZExprError::Invalid(_, e))) => {
+        assert_eq!(e.violation, zvalid::Violation::Rejected(0x1f00));
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
   #[quickcheck]
   fn zexpr_serial_deserial(x: ZExpr) -> bool {
     match ZExpr::deserialize(&ZExpr::serialize(&x)) {
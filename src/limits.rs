@@ -0,0 +1,93 @@
+use core::fmt;
+
+/// Bounds on the work a decoder will do for a single expression, for reading
+/// serialized `ZExpr`s from untrusted sources.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct DecodeLimits {
+  /// How many conses may be nested inside one another. A top-level cons has
+  /// depth 1.
+  pub max_depth: usize,
+  /// The largest data length an atom may declare.
+  pub max_atom_len: u64,
+  /// The largest element count a cons may declare.
+  pub max_cons_len: u64,
+  /// The most bytes one expression may span, headers included.
+  pub max_total_bytes: u64,
+}
+
+impl DecodeLimits {
+  /// No limits beyond those of the input itself.
+  pub fn unlimited() -> Self {
+    DecodeLimits {
+      max_depth: usize::MAX,
+      max_atom_len: u64::MAX,
+      max_cons_len: u64::MAX,
+      max_total_bytes: u64::MAX,
+    }
+  }
+
+  pub(crate) fn check_depth(&self, depth: usize) -> Result<(), DecodeLimit> {
+    if depth > self.max_depth {
+      Err(DecodeLimit::Depth(depth))
+    } else {
+      Ok(())
+    }
+  }
+
+  pub(crate) fn check_atom_len(&self, len: u64) -> Result<(), DecodeLimit> {
+    if len > self.max_atom_len {
+      Err(DecodeLimit::AtomLength(len))
+    } else {
+      Ok(())
+    }
+  }
+
+  pub(crate) fn check_cons_len(&self, len: u64) -> Result<(), DecodeLimit> {
+    if len > self.max_cons_len {
+      Err(DecodeLimit::ConsArity(len))
+    } else {
+      Ok(())
+    }
+  }
+
+  pub(crate) fn check_total(&self, total: u64) -> Result<(), DecodeLimit> {
+    if total > self.max_total_bytes {
+      Err(DecodeLimit::TotalBytes(total))
+    } else {
+      Ok(())
+    }
+  }
+}
+
+/// Defaults suited to data from untrusted peers: 256 levels of nesting,
+/// atoms up to 16 MiB, conses up to 1M elements, expressions up to 64 MiB.
+impl Default for DecodeLimits {
+  fn default() -> Self {
+    DecodeLimits {
+      max_depth: 256,
+      max_atom_len: 16 << 20,
+      max_cons_len: 1 << 20,
+      max_total_bytes: 64 << 20,
+    }
+  }
+}
+
+/// The limit an input broke, with the value it asked for.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum DecodeLimit {
+  Depth(usize),
+  AtomLength(u64),
+  ConsArity(u64),
+  TotalBytes(u64),
+}
+
+impl fmt::Display for DecodeLimit {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Depth(n) => write!(f, "nesting depth {} exceeds limit", n),
+      Self::AtomLength(n) => write!(f, "atom length {} exceeds limit", n),
+      Self::ConsArity(n) => write!(f, "cons length {} exceeds limit", n),
+      Self::TotalBytes(n) => write!(f, "expression size {} exceeds limit", n),
+    }
+  }
+}
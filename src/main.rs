@@ -1,6 +1 @@
-use crate::{zbase, zbase::ZBase::*, ztype};
-use ::zexpr::*;
-use nom::error::ErrorKind;
-use nom::error::ParseError;
-
 fn main() {}
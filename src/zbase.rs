@@ -2,21 +2,53 @@ use core::fmt;
 use nom;
 use nom::error::ErrorKind;
 use nom::error::ParseError;
+use nom::IResult;
 use nom::InputLength;
 use nom::InputTakeAtPosition;
-use nom::{branch::alt, bytes::complete::tag, combinator::value, IResult};
-
-#[derive(PartialEq, Eq, Clone, Copy, Debug)]
-pub enum ZBase {
-  Z2,
-  Z8,
-  Z10,
-  Z16,
-  Z32,
-  Z58,
-  Z64,
+
+/// Defines `ZBase` and its code and alphabet maps from the rows of
+/// `zbase.csv`, which `build.rs` turns into an invocation of this macro.
+macro_rules! zbases {
+  ($(($code:literal, $variant:ident, $name:literal, $digits:literal, $doc:literal)),* $(,)?) => {
+    #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+    pub enum ZBase {
+      $(#[doc = $doc] $variant,)*
+    }
+
+    impl ZBase {
+      /// Every base, in `zbase.csv` order.
+      pub const ALL: &'static [ZBase] = &[$(Self::$variant),*];
+
+      /// The names of the variants, in the same order.
+      pub(crate) const VARIANTS: &'static [&'static str] =
+        &[$(stringify!($variant)),*];
+
+      /// Get the code corresponding to the base algorithm.
+      pub fn code(&self) -> char {
+        match self {
+          $(Self::$variant => $code,)*
+        }
+      }
+
+      pub fn name(&self) -> &'static str {
+        match self {
+          $(Self::$variant => $name,)*
+        }
+      }
+
+      pub fn base_digits(&self) -> &'static str {
+        match self {
+          $(Self::$variant => $digits,)*
+        }
+      }
+    }
+  };
 }
 
+include!(concat!(env!("OUT_DIR"), "/zbases.rs"));
+
+// the table in zbase.csv doesn't say which base is the default
+#[allow(clippy::derivable_impls)]
 impl Default for ZBase {
   fn default() -> Self {
     Self::Z32
@@ -25,15 +57,7 @@ impl Default for ZBase {
 
 impl fmt::Display for ZBase {
   fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-    match self {
-      Self::Z2 => write!(f, "zbase-2"),
-      Self::Z8 => write!(f, "zbase-8"),
-      Self::Z10 => write!(f, "zbase-10"),
-      Self::Z16 => write!(f, "zbase-16"),
-      Self::Z32 => write!(f, "zbase-32"),
-      Self::Z58 => write!(f, "zbase-58"),
-      Self::Z64 => write!(f, "zbase-64"),
-    }
+    write!(f, "zbase-{}", self.base_digits().len())
   }
 }
 
@@ -96,41 +120,9 @@ where
 
 impl ZBase {
   pub fn parse_code(i: &str) -> IResult<&str, Self, ZBaseError<&str>> {
-    alt((
-      value(Self::Z2, tag("b")),
-      value(Self::Z8, tag("o")),
-      value(Self::Z10, tag("d")),
-      value(Self::Z16, tag("x")),
-      value(Self::Z32, tag("v")),
-      value(Self::Z58, tag("I")),
-      value(Self::Z64, tag("~")),
-    ))(i)
-  }
-
-  /// Get the code corresponding to the base algorithm.
-  pub fn code(&self) -> char {
-    match self {
-      Self::Z2 => 'b',
-      Self::Z8 => 'o',
-      Self::Z10 => 'd',
-      Self::Z16 => 'x',
-      Self::Z32 => 'v',
-      Self::Z58 => 'I',
-      Self::Z64 => '~',
-    }
-  }
-
-  pub fn base_digits(&self) -> &str {
-    match self {
-      Self::Z2 => "01",
-      Self::Z8 => "01234567",
-      Self::Z10 => "0123456789",
-      Self::Z16 => "0123456789abcdef",
-      Self::Z32 => "ybndrfg8ejkmcpqxot1uwisza345h769",
-      Self::Z58 => "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz",
-      Self::Z64 => {
-        "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_"
-      }
+    match Self::ALL.iter().find(|base| i.starts_with(base.code())) {
+      Some(base) => Ok((&i[base.code().len_utf8()..], *base)),
+      None => Err(nom::Err::Error(ZBaseError::NomErr(i, ErrorKind::Tag))),
     }
   }
 
@@ -149,9 +141,7 @@ impl ZBase {
     let (i, o) = input.split_at_position_complete(|x| !self.is_digit(x))?;
     match base_x::decode(self.base_digits(), o) {
       Ok(bytes) => Ok((i, bytes)),
-      Err(_) => {
-        Err(nom::Err::Error(ZBaseError::InvalidEncoding(i, self.clone())))
-      }
+      Err(_) => Err(nom::Err::Error(ZBaseError::InvalidEncoding(i, *self))),
     }
   }
 }
@@ -178,16 +168,7 @@ mod tests {
   impl Arbitrary for ZBase {
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
       let x: u32 = g.gen();
-      match x % 7 {
-        0 => ZBase::Z2,
-        1 => ZBase::Z8,
-        2 => ZBase::Z10,
-        3 => ZBase::Z16,
-        4 => ZBase::Z32,
-        5 => ZBase::Z58,
-        6 => ZBase::Z64,
-        _ => panic!("impossible"),
-      }
+      ZBase::ALL[x as usize % ZBase::ALL.len()]
     }
   }
 
@@ -205,4 +186,14 @@ mod tests {
       _ => false,
     }
   }
+
+  #[test]
+  fn zbase_table() {
+    assert_eq!(ZBase::ALL.len(), 7);
+    assert_eq!(ZBase::Z58.code(), '_');
+    assert_eq!(ZBase::Z58.name(), "base58btc");
+    assert_eq!(format!("{}", ZBase::Z64), "zbase-64");
+    assert_eq!(parse("_2g"), Ok(("", (ZBase::Z58, vec![0x61]))));
+    assert!(parse("I2g").is_err());
+  }
 }
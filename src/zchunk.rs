@@ -0,0 +1,655 @@
+//! Large byte strings stored as trees of chunks.
+//!
+//! A `Chunker` cuts a byte string into chunks and stores each as a block
+//! holding one `bytes` atom. Groups of up to `fanout` links are then stored as
+//! branch blocks, `(<len:nat64> <link> <link> ...)`, where `len` counts the
+//! bytes under the branch, and so on up to a single root. A string of one
+//! chunk is its own root. The same bytes and settings always give the same
+//! root link.
+//!
+//! `write_atom` stores the data of an atom of any type this way, e.g. `Text`
+//! by its UTF-8 bytes, so a chunk may end inside a character. The atom's root
+//! is `(<header:bytes> <link>)`: the header `serialize` would write for the
+//! atom, holding its type and length, then the root of its data's chunk tree.
+//! `read_atom` gives the atom back with its type.
+//!
+//! Chunks are either of a fixed size, or cut where a rolling hash of the last
+//! bytes matches a pattern, as in FastCDC. Content-defined cuts move with the
+//! bytes around them, so an edit near the start of a string changes only the
+//! chunks around it, and the other chunks of two versions are stored once.
+//!
+//! A `ChunkReader` streams the bytes back out of any `BlockSource`, holding
+//! one chunk at a time and checking each block against its link as it is
+//! fetched, so a corrupt chunk is caught before any of its bytes are read.
+
+use std::io;
+use std::io::Read;
+use std::mem;
+
+use crate::zdag;
+use crate::zdag::{BlockSource, ZDagError};
+use crate::zlink::ZLink;
+use crate::zstore::BlockStore;
+use crate::ztype::ZType;
+use crate::zvalid::{Invalid, Violation};
+use crate::{ZExpr, ZHeader};
+
+/// Where chunks end.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+enum Cut {
+  Fixed(usize),
+  /// Cut after `min` bytes where the gear hash has no bits set under
+  /// `mask_s` before `avg` bytes, or under the looser `mask_l` after it, and
+  /// always by `max` bytes.
+  Content {
+    min: usize,
+    avg: usize,
+    max: usize,
+    mask_s: u64,
+    mask_l: u64,
+  },
+}
+
+/// How byte strings are cut into chunk trees.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct Chunker {
+  cut: Cut,
+  fanout: usize,
+}
+
+/// Fixed chunks of 256 KiB, and up to 1024 links in a branch.
+impl Default for Chunker {
+  fn default() -> Self {
+    Chunker::new(256 << 10, 1024)
+  }
+}
+
+/// The rolling hash's random value for each byte, from splitmix64.
+const GEAR: [u64; 256] = {
+  let mut table = [0u64; 256];
+  let mut state = 0u64;
+  let mut i = 0;
+  while i < 256 {
+    state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    table[i] = z ^ (z >> 31);
+    i += 1;
+  }
+  table
+};
+
+/// A mask of the top `bits` bits, which depend on the most recent bytes.
+fn top_bits(bits: u32) -> u64 {
+  !0u64 << (64 - bits)
+}
+
+impl Chunker {
+  /// Chunks of `chunk_size` bytes. Panics if `chunk_size` is 0 or `fanout`
+  /// is less than 2.
+  pub fn new(chunk_size: usize, fanout: usize) -> Self {
+    assert!(chunk_size > 0, "chunks must hold at least one byte");
+    Chunker::with_cut(Cut::Fixed(chunk_size), fanout)
+  }
+
+  /// Content-defined chunks of `min` to `max` bytes, and `avg` bytes on
+  /// average. FastCDC suggests `min` and `max` a quarter and four times
+  /// `avg`. Panics unless `0 < min <= avg <= max`, `avg` is a power of two
+  /// of at least 8, and `fanout` is at least 2.
+  pub fn content_defined(
+    min: usize,
+    avg: usize,
+    max: usize,
+    fanout: usize,
+  ) -> Self {
+    assert!(0 < min && min <= avg && avg <= max, "bad chunk size bounds");
+    assert!(avg.is_power_of_two() && avg >= 8, "bad average chunk size");
+    let bits = avg.trailing_zeros();
+    let cut = Cut::Content {
+      min,
+      avg,
+      max,
+      mask_s: top_bits(bits + 2),
+      mask_l: top_bits(bits - 2),
+    };
+    Chunker::with_cut(cut, fanout)
+  }
+
+  fn with_cut(cut: Cut, fanout: usize) -> Self {
+    assert!(fanout > 1, "branches must hold at least two links");
+    Chunker { cut, fanout }
+  }
+
+  /// The most bytes a chunk can hold.
+  pub fn max_chunk_size(&self) -> usize {
+    match self.cut {
+      Cut::Fixed(size) => size,
+      Cut::Content { max, .. } => max,
+    }
+  }
+
+  pub fn fanout(&self) -> usize {
+    self.fanout
+  }
+
+  /// The length of the chunk at the start of `bytes`, which hold at least
+  /// `max_chunk_size` bytes unless they're the end of the string.
+  fn cut(&self, bytes: &[u8]) -> usize {
+    let (min, avg, max, mask_s, mask_l) = match self.cut {
+      Cut::Fixed(size) => return size.min(bytes.len()),
+      Cut::Content {
+        min,
+        avg,
+        max,
+        mask_s,
+        mask_l,
+      } => (min, avg, max, mask_s, mask_l),
+    };
+    let end = max.min(bytes.len());
+    if end <= min {
+      return end;
+    }
+    let mut hash = 0u64;
+    for (i, b) in bytes.iter().enumerate().take(end).skip(min) {
+      hash = (hash << 1).wrapping_add(GEAR[*b as usize]);
+      let mask = if i < avg { mask_s } else { mask_l };
+      if hash & mask == 0 {
+        return i + 1;
+      }
+    }
+    end
+  }
+
+  /// Store everything `r` yields as a chunk tree, returning its root. Only
+  /// one chunk's worth of input and one pending branch per level are held in
+  /// memory.
+  pub fn write<S: BlockStore + ?Sized, R: Read>(
+    &self,
+    store: &mut S,
+    r: R,
+  ) -> Result<ZLink, ZDagError> {
+    Ok(self.write_tree(store, r)?.0)
+  }
+
+  /// Store everything `r` yields as the data of an atom of type `typ`,
+  /// returning the link of the atom's root. Fails with `Invalid`, once the
+  /// chunks are stored, if `typ` is sized and the data is another length.
+  pub fn write_atom<S: BlockStore + ?Sized, R: Read>(
+    &self,
+    store: &mut S,
+    typ: ZType,
+    r: R,
+  ) -> Result<ZLink, ZDagError> {
+    let (data, len) = self.write_tree(store, r)?;
+    match typ.length() {
+      Some(declared) if declared != len => {
+        return Err(ZDagError::Invalid(Invalid {
+          path: vec![],
+          violation: Violation::Length(declared, len as usize),
+        }))
+      }
+      _ => {}
+    }
+    let mut header = vec![];
+    ZHeader::Atom(typ, len)
+      .serialize_into(&mut header)
+      .expect("writing to a Vec cannot fail");
+    let root = vec![ZExpr::Atom(ZType::Bytes(None), header), data.into()];
+    store.put_expr(&ZExpr::Cons(root))
+  }
+
+  /// The (root link, length) of the chunk tree of what `r` yields.
+  fn write_tree<S: BlockStore + ?Sized, R: Read>(
+    &self,
+    store: &mut S,
+    mut r: R,
+  ) -> Result<(ZLink, u64), ZDagError> {
+    let max = self.max_chunk_size();
+    // the (link, length) of the nodes waiting for a parent, by height
+    let mut levels: Vec<Vec<(ZLink, u64)>> = vec![vec![]];
+    let mut buf = Vec::with_capacity(max);
+    loop {
+      let want = (max - buf.len()) as u64;
+      let eof = r.by_ref().take(want).read_to_end(&mut buf)? < want as usize;
+      if buf.is_empty() && !levels[0].is_empty() {
+        break;
+      }
+      let len = self.cut(&buf);
+      let leaf = ZExpr::Atom(ZType::Bytes(None), buf.drain(..len).collect());
+      let node = (store.put_expr(&leaf)?, len as u64);
+      self.push(store, &mut levels, 0, node)?;
+      if eof && buf.is_empty() {
+        break;
+      }
+    }
+    // close the partial branches, bottom up
+    let mut height = 0;
+    while height + 1 < levels.len() || levels[height].len() > 1 {
+      if !levels[height].is_empty() {
+        let node = branch(store, &mut levels[height])?;
+        self.push(store, &mut levels, height + 1, node)?;
+      }
+      height += 1;
+    }
+    Ok(levels[height][0])
+  }
+
+  /// Store `bytes` as a chunk tree, returning its root.
+  pub fn write_bytes<S: BlockStore + ?Sized>(
+    &self,
+    store: &mut S,
+    bytes: &[u8],
+  ) -> Result<ZLink, ZDagError> {
+    self.write(store, bytes)
+  }
+
+  /// Add a node at `height`, storing the branch it completes.
+  fn push<S: BlockStore + ?Sized>(
+    &self,
+    store: &mut S,
+    levels: &mut Vec<Vec<(ZLink, u64)>>,
+    mut height: usize,
+    mut node: (ZLink, u64),
+  ) -> Result<(), ZDagError> {
+    loop {
+      if levels.len() == height {
+        levels.push(vec![]);
+      }
+      levels[height].push(node);
+      if levels[height].len() < self.fanout {
+        return Ok(());
+      }
+      node = branch(store, &mut levels[height])?;
+      height += 1;
+    }
+  }
+}
+
+/// Store a branch over `nodes`, emptying them.
+fn branch<S: BlockStore + ?Sized>(
+  store: &mut S,
+  nodes: &mut Vec<(ZLink, u64)>,
+) -> Result<(ZLink, u64), ZDagError> {
+  let len = nodes.iter().map(|(_, len)| len).sum::<u64>();
+  let mut xs = Vec::with_capacity(nodes.len() + 1);
+  xs.push(ZExpr::Atom(ZType::Nat(Some(8)), len.to_be_bytes().to_vec()));
+  xs.extend(nodes.drain(..).map(|(link, _)| link.into()));
+  Ok((store.put_expr(&ZExpr::Cons(xs))?, len))
+}
+
+enum Node {
+  Leaf(Vec<u8>),
+  Branch(u64, Vec<ZLink>),
+}
+
+fn load_node<S: BlockSource + ?Sized>(
+  src: &S,
+  link: &ZLink,
+) -> Result<Node, ZDagError> {
+  let bad = || ZDagError::BadChunk(*link);
+  match &mut zdag::load(src, link)? {
+    ZExpr::Atom(ZType::Bytes(_), dat) => Ok(Node::Leaf(mem::take(dat))),
+    ZExpr::Cons(xs) => match xs.split_first() {
+      Some((ZExpr::Atom(ZType::Nat(Some(8)), len), links))
+        if len.len() == 8 && !links.is_empty() =>
+      {
+        let mut be = [0u8; 8];
+        be.copy_from_slice(len);
+        let links = links
+          .iter()
+          .map(ZLink::from_zexpr)
+          .collect::<Option<Vec<_>>>()
+          .ok_or_else(bad)?;
+        Ok(Node::Branch(u64::from_be_bytes(be), links))
+      }
+      _ => Err(bad()),
+    },
+    _ => Err(bad()),
+  }
+}
+
+/// Read back an atom stored by `Chunker::write_atom`, with its type.
+pub fn read_atom<S: BlockSource + ?Sized>(
+  src: &S,
+  root: &ZLink,
+) -> Result<ZExpr, ZDagError> {
+  let bad = || ZDagError::BadChunk(*root);
+  let (typ, len, data) = match &zdag::load(src, root)? {
+    ZExpr::Cons(xs) => match xs.as_slice() {
+      [ZExpr::Atom(ZType::Bytes(None), header), data] => {
+        match (
+          ZHeader::deserialize_canonical(header),
+          ZLink::from_zexpr(data),
+        ) {
+          (Ok(([], ZHeader::Atom(typ, len))), Some(data)) => (typ, len, data),
+          _ => return Err(bad()),
+        }
+      }
+      _ => return Err(bad()),
+    },
+    _ => return Err(bad()),
+  };
+  let mut reader = ChunkReader::open(src, &data)?;
+  if reader.len() != len {
+    return Err(bad());
+  }
+  // every branch's length is checked as it's finished, so this ends up
+  // holding `len` bytes
+  let mut dat = mem::take(&mut reader.chunk);
+  while reader.next_chunk()? {
+    dat.extend_from_slice(&reader.chunk);
+  }
+  Ok(ZExpr::Atom(typ, dat))
+}
+
+/// A branch being read: (its link, its declared length, the offset it
+/// starts at, the links still to read).
+type Frame = (ZLink, u64, u64, std::vec::IntoIter<ZLink>);
+
+/// Reads the bytes of a chunk tree in order, verifying every block as it is
+/// fetched. Errors are `io::Error`s of kind `InvalidData` wrapping a
+/// `ZDagError`, except for those of the source's own IO.
+pub struct ChunkReader<'a, S: ?Sized> {
+  src: &'a S,
+  len: u64,
+  stack: Vec<Frame>,
+  chunk: Vec<u8>,
+  pos: usize,
+  /// The bytes in the chunks fetched so far
+  offset: u64,
+}
+
+impl<'a, S: BlockSource + ?Sized> ChunkReader<'a, S> {
+  /// Fetch the root of the tree at `root`.
+  pub fn open(src: &'a S, root: &ZLink) -> Result<Self, ZDagError> {
+    let mut reader = ChunkReader {
+      src,
+      len: 0,
+      stack: vec![],
+      chunk: vec![],
+      pos: 0,
+      offset: 0,
+    };
+    reader.len = match load_node(src, root)? {
+      Node::Leaf(chunk) => {
+        reader.offset = chunk.len() as u64;
+        reader.chunk = chunk;
+        reader.offset
+      }
+      Node::Branch(len, links) => {
+        reader.stack.push((*root, len, 0, links.into_iter()));
+        len
+      }
+    };
+    Ok(reader)
+  }
+
+  /// The length of the whole byte string, as the root declares it.
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// Fetch the next chunk, returning false at the end of the tree.
+  fn next_chunk(&mut self) -> Result<bool, ZDagError> {
+    loop {
+      let (branch, len, start, links) = match self.stack.last_mut() {
+        None => return Ok(false),
+        Some(frame) => frame,
+      };
+      match links.next() {
+        Some(link) => match load_node(self.src, &link)? {
+          Node::Leaf(chunk) => {
+            self.offset += chunk.len() as u64;
+            self.chunk = chunk;
+            self.pos = 0;
+            return Ok(true);
+          }
+          Node::Branch(len, links) => {
+            self.stack.push((link, len, self.offset, links.into_iter()))
+          }
+        },
+        None => {
+          if self.offset - *start != *len {
+            return Err(ZDagError::BadChunk(*branch));
+          }
+          self.stack.pop();
+        }
+      }
+    }
+  }
+}
+
+impl<'a, S: BlockSource + ?Sized> Read for ChunkReader<'a, S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    while self.pos == self.chunk.len() {
+      match self.next_chunk() {
+        Ok(true) => {}
+        Ok(false) => return Ok(0),
+        Err(ZDagError::Io(e)) => return Err(e),
+        Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+      }
+    }
+    let n = buf.len().min(self.chunk.len() - self.pos);
+    buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+    self.pos += n;
+    Ok(n)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zstore::MemoryStore;
+
+  fn blob(len: usize) -> Vec<u8> {
+    (0..len).map(|i| (i * 7 % 251) as u8).collect()
+  }
+
+  fn read_all(store: &MemoryStore, root: &ZLink) -> io::Result<Vec<u8>> {
+    let mut reader = ChunkReader::open(store, root).unwrap();
+    let mut out = vec![];
+    reader.read_to_end(&mut out)?;
+    assert_eq!(reader.len(), out.len() as u64);
+    Ok(out)
+  }
+
+  fn bad_chunk(e: io::Error) -> ZLink {
+    match e.into_inner().unwrap().downcast::<ZDagError>().map(|e| *e) {
+      Ok(ZDagError::BadChunk(link)) | Ok(ZDagError::Corrupt(link)) => link,
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
+  #[test]
+  fn zchunk_roundtrip() {
+    let chunker = Chunker::new(4, 3);
+    for len in &[0, 1, 4, 5, 12, 13, 36, 37, 100] {
+      let mut store = MemoryStore::new();
+      let bytes = blob(*len);
+      let root = chunker.write_bytes(&mut store, &bytes).unwrap();
+      assert_eq!(read_all(&store, &root).unwrap(), bytes);
+      // the tree depends only on the bytes, not how they're read in
+      let reader = io::Cursor::new(&bytes).chain(&[][..]);
+      assert_eq!(chunker.write(&mut store, reader).unwrap(), root);
+    }
+    // a single chunk is just a bytes atom
+    let mut store = MemoryStore::new();
+    let root = chunker.write_bytes(&mut store, b"abc").unwrap();
+    let leaf = ZExpr::Atom(ZType::Bytes(None), b"abc".to_vec());
+    assert_eq!(root, ZLink::of(&leaf).unwrap());
+    // 10 chunks need three levels of branches with a fanout of 3
+    let mut store = MemoryStore::new();
+    chunker.write_bytes(&mut store, &blob(40)).unwrap();
+    assert_eq!(store.len(), 10 + 4 + 2 + 1);
+    // identical chunks are stored once
+    let mut store = MemoryStore::new();
+    chunker.write_bytes(&mut store, &[0; 40]).unwrap();
+    assert_eq!(store.len(), 1 + 2 + 2 + 1);
+  }
+
+  #[test]
+  fn zchunk_corrupt() {
+    let chunker = Chunker::new(4, 2);
+    let mut store = MemoryStore::new();
+    let bytes = blob(16);
+    let root = chunker.write_bytes(&mut store, &bytes).unwrap();
+    // swap the last chunk's bytes for another block's
+    let last =
+      ZLink::of(&ZExpr::Atom(ZType::Bytes(None), bytes[12..].to_vec()))
+        .unwrap();
+    let mut blocks: std::collections::BTreeMap<ZLink, Vec<u8>> = store
+      .list()
+      .unwrap()
+      .into_iter()
+      .map(|l| (l, store.get(&l).unwrap().unwrap()))
+      .collect();
+    blocks.insert(last, blocks[&root].clone());
+    let mut reader = ChunkReader::open(&blocks, &root).unwrap();
+    // the good chunks stream out before the bad one is reached
+    let mut good = vec![0; 12];
+    reader.read_exact(&mut good).unwrap();
+    assert_eq!(good, bytes[..12]);
+    let e = reader.read_to_end(&mut vec![]).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(bad_chunk(e), last);
+
+    // a well-hashed branch whose length is wrong
+    let leaf = store.put_expr(&ZExpr::Atom(ZType::Bytes(None), vec![1, 2]));
+    let lie = ZExpr::Cons(vec![
+      ZExpr::Atom(ZType::Nat(Some(8)), 3u64.to_be_bytes().to_vec()),
+      leaf.unwrap().into(),
+    ]);
+    let lie = store.put_expr(&lie).unwrap();
+    assert_eq!(bad_chunk(read_all(&store, &lie).unwrap_err()), lie);
+    // a block which isn't a chunk tree node
+    let text = ZExpr::Cons(vec![ZExpr::Atom(ZType::Text(None), vec![])]);
+    let text = store.put_expr(&text).unwrap();
+    match ChunkReader::open(&store, &text) {
+      Err(ZDagError::BadChunk(l)) => assert_eq!(l, text),
+      y => panic!("unexpected {:?}", y.map(|r| r.len())),
+    }
+    match ChunkReader::open(&MemoryStore::new(), &root) {
+      Err(ZDagError::Missing(l)) => assert_eq!(l, root),
+      y => panic!("unexpected {:?}", y.map(|r| r.len())),
+    }
+  }
+
+  /// Random bytes, the same every run.
+  fn noise(len: usize) -> Vec<u8> {
+    let mut x = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+      .map(|_| {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        x as u8
+      })
+      .collect()
+  }
+
+  /// Yields a few bytes per read.
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      let n = buf.len().min(self.0.len()).min(7);
+      buf[..n].copy_from_slice(&self.0[..n]);
+      self.0 = &self.0[n..];
+      Ok(n)
+    }
+  }
+
+  fn leaves(store: &MemoryStore) -> Vec<Vec<u8>> {
+    let mut leaves = vec![];
+    for link in store.list().unwrap() {
+      if let Node::Leaf(chunk) = load_node(store, &link).unwrap() {
+        leaves.push(chunk);
+      }
+    }
+    leaves
+  }
+
+  #[test]
+  fn zchunk_content_defined() {
+    let chunker = Chunker::content_defined(256, 1024, 4096, 16);
+    let bytes = noise(256 << 10);
+    let mut store = MemoryStore::new();
+    let root = chunker.write_bytes(&mut store, &bytes).unwrap();
+    assert_eq!(read_all(&store, &root).unwrap(), bytes);
+    assert_eq!(chunker.write(&mut store, Trickle(&bytes)).unwrap(), root);
+    let chunks = leaves(&store);
+    assert!(chunks.iter().all(|c| c.len() <= 4096));
+    assert!(chunks.iter().filter(|c| c.len() < 256).count() <= 1);
+    assert!(chunks.len() > 128 && chunks.len() < 512);
+
+    // an insertion near the start and an edit in the middle change only the
+    // chunks around them
+    let mut edited = bytes.clone();
+    edited[128 << 10] ^= 0xff;
+    edited.splice(100..100, b"inserted".iter().copied());
+    let root = chunker.write_bytes(&mut store, &edited).unwrap();
+    assert_eq!(read_all(&store, &root).unwrap(), edited);
+    assert!(leaves(&store).len() - chunks.len() <= 4);
+
+    // whereas fixed chunks all shift
+    let fixed = Chunker::new(1024, 16);
+    let mut store = MemoryStore::new();
+    fixed.write_bytes(&mut store, &bytes).unwrap();
+    fixed.write_bytes(&mut store, &edited).unwrap();
+    assert!(leaves(&store).len() > 500);
+  }
+
+  #[test]
+  fn zchunk_atoms() {
+    let chunker = Chunker::content_defined(16, 64, 256, 4);
+    let text: String =
+      (0..2000).map(|i| ["λ", "x", "→", "🦀"][i % 4]).collect();
+    let x = ZExpr::Atom(ZType::Text(None), text.clone().into_bytes());
+    let mut store = MemoryStore::new();
+    let root = chunker
+      .write_atom(&mut store, ZType::Text(None), text.as_bytes())
+      .unwrap();
+    assert_eq!(read_atom(&store, &root).unwrap(), x);
+    // the data is the same tree as the bytes alone, and more than one chunk
+    let data = chunker.write_bytes(&mut store, text.as_bytes()).unwrap();
+    assert!(matches!(load_node(&store, &data), Ok(Node::Branch(..))));
+    match &zdag::load(&store, &root).unwrap() {
+      ZExpr::Cons(xs) => assert_eq!(ZLink::from_zexpr(&xs[1]), Some(data)),
+      y => panic!("unexpected {:?}", y),
+    }
+    // a plain chunk tree has no type
+    match read_atom(&store, &data) {
+      Err(ZDagError::BadChunk(l)) => assert_eq!(l, data),
+      y => panic!("unexpected {:?}", y),
+    }
+
+    // sized types keep their length
+    let typ = ZType::Nat(Some(4));
+    let root = chunker
+      .write_atom(&mut store, typ, &[1, 2, 3, 4][..])
+      .unwrap();
+    assert_eq!(
+      read_atom(&store, &root).unwrap(),
+      ZExpr::Atom(typ, vec![1, 2, 3, 4])
+    );
+    match chunker.write_atom(&mut store, typ, &[1, 2, 3][..]) {
+      Err(ZDagError::Invalid(e)) => {
+        assert_eq!(e.violation, Violation::Length(4, 3))
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+    // and the empty atom works too
+    let root = chunker
+      .write_atom(&mut store, ZType::Text(None), &b""[..])
+      .unwrap();
+    assert_eq!(
+      read_atom(&store, &root).unwrap(),
+      ZExpr::Atom(ZType::Text(None), vec![])
+    );
+  }
+}
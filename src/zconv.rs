@@ -0,0 +1,400 @@
+//! Conversions between atoms and native Rust values.
+//!
+//! These follow the mapping of the serde format in `zserde`: numbers are
+//! big-endian, `Int`s are two's complement, and the type's width is the size
+//! of the Rust type, so `7u16` becomes `x0007:nat16`. Reading a number back
+//! accepts any width as long as the value fits.
+//!
+//! | Rust                | atom                                  |
+//! |---------------------|---------------------------------------|
+//! | `bool`              | `true:symbol` or `false:symbol`       |
+//! | `u8` .. `u128`      | `nat8` .. `nat128`                    |
+//! | `i8` .. `i128`      | `int8` .. `int128`                    |
+//! | `f32`, `f64`        | `float32`, `float64`                  |
+//! | `char`              | `char32`, the scalar value            |
+//! | `String`, `&str`    | `text`                                |
+//! | `Vec<u8>`, `&[u8]`  | `bytes`                               |
+
+use core::fmt;
+use std::convert::TryFrom;
+
+use crate::ztype::ZType;
+use crate::ZExpr;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ZConvError {
+  /// The expression isn't the kind of atom asked for: (expected, found)
+  Mismatch(&'static str, String),
+  /// The number doesn't fit the named Rust type
+  OutOfRange(&'static str),
+  /// Text or a symbol which isn't UTF-8: (what it is)
+  InvalidUtf8(&'static str),
+}
+
+impl fmt::Display for ZConvError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Mismatch(expected, found) => {
+        write!(f, "expected {}, found {}", expected, found)
+      }
+      Self::OutOfRange(ty) => write!(f, "number out of range for {}", ty),
+      Self::InvalidUtf8(what) => write!(f, "invalid utf8 {}", what),
+    }
+  }
+}
+
+impl std::error::Error for ZConvError {}
+
+/// Describe a `ZExpr` for error messages.
+pub(crate) fn describe(x: &ZExpr) -> String {
+  match x {
+    ZExpr::Atom(ty, _) => format!("{}", ty),
+    ZExpr::Cons(_) => String::from("cons"),
+  }
+}
+
+/// Read a big-endian unsigned integer, ignoring leading zero bytes.
+pub(crate) fn be_u128(dat: &[u8]) -> Option<u128> {
+  let start = dat.iter().position(|&b| b != 0).unwrap_or(dat.len());
+  if dat.len() - start > 16 {
+    return None;
+  }
+  Some(
+    dat[start..]
+      .iter()
+      .fold(0, |acc, &b| (acc << 8) | b as u128),
+  )
+}
+
+/// Read a big-endian two's complement integer, ignoring redundant sign bytes.
+pub(crate) fn be_i128(dat: &[u8]) -> Option<i128> {
+  let neg = dat.first().is_some_and(|&b| b & 0x80 != 0);
+  let sign = if neg { 0xff } else { 0x00 };
+  let start = dat.iter().position(|&b| b != sign).unwrap_or(dat.len());
+  // keep one sign byte when the first significant byte has the wrong high bit
+  let start = match dat.get(start) {
+    Some(&b) if start > 0 && (b & 0x80 != 0) != neg => start - 1,
+    _ => start,
+  };
+  if dat.len() - start > 16 {
+    return None;
+  }
+  let init: i128 = if neg { -1 } else { 0 };
+  Some(
+    dat[start..]
+      .iter()
+      .fold(init, |acc, &b| (acc << 8) | b as i128),
+  )
+}
+
+macro_rules! nat_conv {
+  ($ty:ty) => {
+    impl From<$ty> for ZExpr {
+      fn from(v: $ty) -> Self {
+        let width = std::mem::size_of::<$ty>() as u64;
+        ZExpr::Atom(ZType::Nat(Some(width)), v.to_be_bytes().to_vec())
+      }
+    }
+
+    impl TryFrom<&ZExpr> for $ty {
+      type Error = ZConvError;
+
+      fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+        let range = || ZConvError::OutOfRange(stringify!($ty));
+        match x {
+          ZExpr::Atom(ZType::Nat(_), dat) => {
+            let n = be_u128(dat).ok_or_else(range)?;
+            <$ty>::try_from(n).map_err(|_| range())
+          }
+          _ => Err(ZConvError::Mismatch("nat", describe(x))),
+        }
+      }
+    }
+  };
+}
+
+macro_rules! int_conv {
+  ($ty:ty) => {
+    impl From<$ty> for ZExpr {
+      fn from(v: $ty) -> Self {
+        let width = std::mem::size_of::<$ty>() as u64;
+        ZExpr::Atom(ZType::Int(Some(width)), v.to_be_bytes().to_vec())
+      }
+    }
+
+    impl TryFrom<&ZExpr> for $ty {
+      type Error = ZConvError;
+
+      fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+        let range = || ZConvError::OutOfRange(stringify!($ty));
+        match x {
+          ZExpr::Atom(ZType::Int(_), dat) => {
+            let n = be_i128(dat).ok_or_else(range)?;
+            <$ty>::try_from(n).map_err(|_| range())
+          }
+          _ => Err(ZConvError::Mismatch("int", describe(x))),
+        }
+      }
+    }
+  };
+}
+
+nat_conv!(u8);
+nat_conv!(u16);
+nat_conv!(u32);
+nat_conv!(u64);
+nat_conv!(u128);
+int_conv!(i8);
+int_conv!(i16);
+int_conv!(i32);
+int_conv!(i64);
+int_conv!(i128);
+
+impl From<f32> for ZExpr {
+  fn from(v: f32) -> Self {
+    ZExpr::Atom(ZType::Float(Some(4)), v.to_be_bytes().to_vec())
+  }
+}
+
+impl From<f64> for ZExpr {
+  fn from(v: f64) -> Self {
+    ZExpr::Atom(ZType::Float(Some(8)), v.to_be_bytes().to_vec())
+  }
+}
+
+impl TryFrom<&ZExpr> for f32 {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Float(_), dat) if dat.len() == 4 => {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(dat);
+        Ok(f32::from_be_bytes(buf))
+      }
+      _ => Err(ZConvError::Mismatch("float32", describe(x))),
+    }
+  }
+}
+
+/// Also reads `float32`s, which widen exactly.
+impl TryFrom<&ZExpr> for f64 {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Float(_), dat) if dat.len() == 8 => {
+        let mut buf = [0; 8];
+        buf.copy_from_slice(dat);
+        Ok(f64::from_be_bytes(buf))
+      }
+      ZExpr::Atom(ZType::Float(_), dat) if dat.len() == 4 => {
+        f32::try_from(x).map(f64::from)
+      }
+      _ => Err(ZConvError::Mismatch("float64", describe(x))),
+    }
+  }
+}
+
+impl From<char> for ZExpr {
+  fn from(v: char) -> Self {
+    ZExpr::Atom(ZType::Char(Some(4)), (v as u32).to_be_bytes().to_vec())
+  }
+}
+
+impl TryFrom<&ZExpr> for char {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Char(_), dat) => {
+        match be_u128(dat).and_then(|n| std::char::from_u32(n as u32)) {
+          Some(c) if dat.len() <= 4 => Ok(c),
+          _ => Err(ZConvError::OutOfRange("char")),
+        }
+      }
+      _ => Err(ZConvError::Mismatch("char", describe(x))),
+    }
+  }
+}
+
+impl From<bool> for ZExpr {
+  fn from(v: bool) -> Self {
+    let name = if v { "true" } else { "false" };
+    ZExpr::Atom(ZType::Symbol(None), name.as_bytes().to_vec())
+  }
+}
+
+impl TryFrom<&ZExpr> for bool {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Symbol(_), dat) if dat == b"true" => Ok(true),
+      ZExpr::Atom(ZType::Symbol(_), dat) if dat == b"false" => Ok(false),
+      _ => Err(ZConvError::Mismatch("bool symbol", describe(x))),
+    }
+  }
+}
+
+impl From<&str> for ZExpr {
+  fn from(v: &str) -> Self {
+    ZExpr::Atom(ZType::Text(None), v.as_bytes().to_vec())
+  }
+}
+
+impl From<String> for ZExpr {
+  fn from(v: String) -> Self {
+    ZExpr::Atom(ZType::Text(None), v.into_bytes())
+  }
+}
+
+impl<'a> TryFrom<&'a ZExpr> for &'a str {
+  type Error = ZConvError;
+
+  fn try_from(x: &'a ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Text(_), dat) => {
+        std::str::from_utf8(dat).map_err(|_| ZConvError::InvalidUtf8("text"))
+      }
+      _ => Err(ZConvError::Mismatch("text", describe(x))),
+    }
+  }
+}
+
+impl TryFrom<&ZExpr> for String {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    <&str>::try_from(x).map(String::from)
+  }
+}
+
+impl From<&[u8]> for ZExpr {
+  fn from(v: &[u8]) -> Self {
+    ZExpr::Atom(ZType::Bytes(None), v.to_vec())
+  }
+}
+
+impl From<Vec<u8>> for ZExpr {
+  fn from(v: Vec<u8>) -> Self {
+    ZExpr::Atom(ZType::Bytes(None), v)
+  }
+}
+
+/// Also reads the digests, keys and signatures, which are bytes too.
+impl<'a> TryFrom<&'a ZExpr> for &'a [u8] {
+  type Error = ZConvError;
+
+  fn try_from(x: &'a ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Bytes(_), dat)
+      | ZExpr::Atom(ZType::Hash(_), dat)
+      | ZExpr::Atom(ZType::Sha256(_), dat)
+      | ZExpr::Atom(ZType::Sha512(_), dat)
+      | ZExpr::Atom(ZType::Pubkey(_), dat)
+      | ZExpr::Atom(ZType::Signature(_), dat) => Ok(dat),
+      _ => Err(ZConvError::Mismatch("bytes", describe(x))),
+    }
+  }
+}
+
+impl TryFrom<&ZExpr> for Vec<u8> {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    <&[u8]>::try_from(x).map(<[u8]>::to_vec)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+
+  fn atom(typ: ZType, dat: &[u8]) -> ZExpr {
+    ZExpr::Atom(typ, dat.to_vec())
+  }
+
+  #[test]
+  fn zconv_numbers() {
+    assert_eq!(ZExpr::from(7u16), atom(Nat(Some(2)), &[0, 7]));
+    assert_eq!(
+      ZExpr::from(-2i32),
+      atom(Int(Some(4)), &[0xff, 0xff, 0xff, 0xfe])
+    );
+    assert_eq!(
+      ZExpr::from(1.0f32),
+      atom(Float(Some(4)), &[0x3f, 0x80, 0, 0])
+    );
+    assert_eq!(u128::try_from(&ZExpr::from(u128::MAX)), Ok(u128::MAX));
+    assert_eq!(i128::try_from(&ZExpr::from(i128::MIN)), Ok(i128::MIN));
+    assert_eq!(i8::try_from(&ZExpr::from(-128i64)), Ok(-128));
+    assert_eq!(f64::try_from(&ZExpr::from(0.1f64)), Ok(0.1));
+    assert_eq!(f64::try_from(&ZExpr::from(0.5f32)), Ok(0.5));
+    // any width is read, as long as the value fits
+    assert_eq!(u8::try_from(&atom(Nat(None), &[0, 0, 200])), Ok(200));
+    assert_eq!(u64::try_from(&atom(Nat(None), &[])), Ok(0));
+    assert_eq!(
+      u8::try_from(&ZExpr::from(256u16)),
+      Err(ZConvError::OutOfRange("u8"))
+    );
+    assert_eq!(
+      i8::try_from(&ZExpr::from(128i16)),
+      Err(ZConvError::OutOfRange("i8"))
+    );
+    assert_eq!(
+      u64::try_from(&ZExpr::from(1i64)),
+      Err(ZConvError::Mismatch("nat", String::from("int64")))
+    );
+    assert_eq!(
+      f32::try_from(&ZExpr::from(1.0f64)),
+      Err(ZConvError::Mismatch("float32", String::from("float64")))
+    );
+  }
+
+  #[test]
+  fn zconv_others() {
+    assert_eq!(ZExpr::from('a'), atom(Char(Some(4)), &[0, 0, 0, 0x61]));
+    assert_eq!(char::try_from(&ZExpr::from('λ')), Ok('λ'));
+    assert_eq!(
+      char::try_from(&atom(Char(None), &[0xd8, 0])),
+      Err(ZConvError::OutOfRange("char"))
+    );
+    assert_eq!(bool::try_from(&ZExpr::from(true)), Ok(true));
+    assert_eq!(bool::try_from(&ZExpr::from(false)), Ok(false));
+    assert!(bool::try_from(&atom(Symbol(None), b"yes")).is_err());
+    let text = ZExpr::from("hi");
+    assert_eq!(text, ZExpr::from(String::from("hi")));
+    assert_eq!(<&str>::try_from(&text), Ok("hi"));
+    assert_eq!(String::try_from(&text), Ok(String::from("hi")));
+    assert_eq!(
+      String::try_from(&atom(Text(None), b"\xff")),
+      Err(ZConvError::InvalidUtf8("text"))
+    );
+    let bytes = ZExpr::from(&b"\x00\x01"[..]);
+    assert_eq!(bytes, ZExpr::from(vec![0, 1]));
+    assert_eq!(<&[u8]>::try_from(&bytes), Ok(&[0, 1][..]));
+    assert_eq!(
+      Vec::<u8>::try_from(&ZExpr::Cons(vec![])),
+      Err(ZConvError::Mismatch("bytes", String::from("cons")))
+    );
+  }
+
+  /// Values converted either way are read back by serde, and vice versa.
+  #[quickcheck]
+  fn zconv_serde(a: u64, b: i32, c: f64, d: char, e: String, f: bool) -> bool {
+    crate::from_zexpr::<u64>(&ZExpr::from(a)) == Ok(a)
+      && crate::from_zexpr::<i32>(&ZExpr::from(b)) == Ok(b)
+      && crate::from_zexpr::<f64>(&ZExpr::from(c)).map(f64::to_bits)
+        == Ok(c.to_bits())
+      && crate::from_zexpr::<char>(&ZExpr::from(d)) == Ok(d)
+      && crate::from_zexpr::<String>(&ZExpr::from(e.clone())) == Ok(e.clone())
+      && crate::from_zexpr::<bool>(&ZExpr::from(f)) == Ok(f)
+      && u64::try_from(&crate::to_zexpr(&a).unwrap()) == Ok(a)
+      && i32::try_from(&crate::to_zexpr(&b).unwrap()) == Ok(b)
+      && char::try_from(&crate::to_zexpr(&d).unwrap()) == Ok(d)
+      && String::try_from(&crate::to_zexpr(&e).unwrap()) == Ok(e)
+      && bool::try_from(&crate::to_zexpr(&f).unwrap()) == Ok(f)
+  }
+}
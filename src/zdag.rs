@@ -0,0 +1,483 @@
+//! Storing a `ZExpr` as a Merkle DAG of blocks.
+//!
+//! `split` cuts an expression into blocks bottom-up: every cons whose
+//! serialization, after its own large children have been cut out, is longer
+//! than a threshold becomes a block and is replaced in its parent by its
+//! `ZLink`. Identical subtrees hash to the same link, so they are stored once.
+//!
+//! The resolvers reverse this against any `BlockSource`, checking each block
+//! against its link as it is loaded. Every `link` atom is followed, whatever
+//! its algorithm, while atoms holding only a digest are left alone. A few
+//! blocks can link to one another often enough to resolve to a tree too big
+//! to hold, so blocks from untrusted sources should be resolved with
+//! `resolve_limited`.
+
+use core::fmt;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+
+use nom::Err;
+
+use crate::limits::{DecodeLimit, DecodeLimits};
+use crate::zlink::ZLink;
+use crate::zvalid::Invalid;
+use crate::{ZExpr, ZExprDeserialError, ZHeader};
+
+#[derive(Debug)]
+pub enum ZDagError {
+  /// No block was found for the link
+  Missing(ZLink),
+  /// The block's bytes don't hash to its link
+  Corrupt(ZLink),
+  /// The block isn't exactly one canonically serialized expression: (link,
+  /// offset of the first bad byte)
+  Malformed(ZLink, usize),
+  /// The path doesn't lead to an expression: (the path up to the bad index)
+  BadPath(Vec<usize>),
+  /// The block isn't a node of a chunk tree, or holds a different number of
+  /// bytes than it declares
+  BadChunk(ZLink),
+  /// An expression to be stored has a sized atom whose data is another
+  /// length, so it has no link. See `ZLink::of`
+  Invalid(Invalid),
+  /// A block, or the tree resolved from the blocks, broke the resolver's
+  /// limits
+  LimitExceeded(DecodeLimit),
+  Io(io::Error),
+}
+
+impl fmt::Display for ZDagError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Missing(link) => write!(f, "block {} not found", link),
+      Self::Corrupt(link) => write!(f, "block {} fails its hash check", link),
+      Self::Malformed(link, pos) => {
+        write!(f, "block {} is malformed at byte {}", link, pos)
+      }
+      Self::BadPath(path) => write!(f, "no expression at path {:?}", path),
+      Self::BadChunk(link) => write!(f, "block {} is a bad chunk", link),
+      Self::Invalid(e) => write!(f, "{}", e),
+      Self::LimitExceeded(e) => write!(f, "{}", e),
+      Self::Io(e) => write!(f, "{}", e),
+    }
+  }
+}
+
+impl std::error::Error for ZDagError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Invalid(e) => Some(e),
+      Self::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<Invalid> for ZDagError {
+  fn from(e: Invalid) -> Self {
+    ZDagError::Invalid(e)
+  }
+}
+
+impl From<io::Error> for ZDagError {
+  fn from(e: io::Error) -> Self {
+    ZDagError::Io(e)
+  }
+}
+
+/// Somewhere blocks can be fetched from by their link.
+pub trait BlockSource {
+  /// The bytes stored under `link`, or `None` if there are none. The bytes
+  /// are checked against the link by the caller.
+  fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError>;
+}
+
+impl BlockSource for HashMap<ZLink, Vec<u8>> {
+  fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    Ok(self.get(link).cloned())
+  }
+}
+
+impl BlockSource for BTreeMap<ZLink, Vec<u8>> {
+  fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    Ok(self.get(link).cloned())
+  }
+}
+
+impl<S: BlockSource + ?Sized> BlockSource for &S {
+  fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    (**self).get_block(link)
+  }
+}
+
+/// Cut `x` into blocks, replacing each cons which would serialize to more
+/// than `threshold` bytes with its link. Returns the expression left at the
+/// root, which is itself a link if the whole of `x` became a block, and the
+/// blocks by link. Fails, like `ZLink::of`, if a sized atom in `x` has data of
+/// another length.
+pub fn split(
+  x: &ZExpr,
+  threshold: usize,
+) -> Result<(ZExpr, BTreeMap<ZLink, Vec<u8>>), Invalid> {
+  x.check_lengths()?;
+  let mut blocks = BTreeMap::new();
+  // the conses being rebuilt, with the elements still to visit
+  let mut stack: Vec<(Vec<ZExpr>, std::slice::Iter<ZExpr>)> = vec![];
+  let mut next = x;
+  loop {
+    match next {
+      ZExpr::Atom(typ, dat) => {
+        let y = ZExpr::Atom(*typ, dat.clone());
+        match stack.last_mut() {
+          None => return Ok((y, blocks)),
+          Some((ys, _)) => ys.push(y),
+        }
+      }
+      ZExpr::Cons(xs) => stack.push((Vec::with_capacity(xs.len()), xs.iter())),
+    }
+    // find the next element, cutting out the conses which are done
+    next = loop {
+      let (_, rest) = stack.last_mut().unwrap();
+      if let Some(x) = rest.next() {
+        break x;
+      }
+      let (ys, _) = stack.pop().unwrap();
+      let mut y = ZExpr::Cons(ys);
+      if y.encoded_len() > threshold {
+        let bytes = y.serialize();
+        let link = ZLink::of_bytes(&bytes);
+        blocks.entry(link).or_insert(bytes);
+        y = link.into();
+      }
+      match stack.last_mut() {
+        None => return Ok((y, blocks)),
+        Some((ys, _)) => ys.push(y),
+      }
+    };
+  }
+}
+
+/// Fetch the block for `link` and decode it, without resolving the links
+/// inside it.
+pub fn load<S: BlockSource + ?Sized>(
+  src: &S,
+  link: &ZLink,
+) -> Result<ZExpr, ZDagError> {
+  load_limited(src, link, &DecodeLimits::unlimited())
+}
+
+fn load_limited<S: BlockSource + ?Sized>(
+  src: &S,
+  link: &ZLink,
+  limits: &DecodeLimits,
+) -> Result<ZExpr, ZDagError> {
+  let bytes = src.get_block(link)?.ok_or(ZDagError::Missing(*link))?;
+  decode_limited(link, &bytes, limits)
+}
+
+/// Check that `bytes` are the block for `link`, and decode them.
+pub fn decode_block(link: &ZLink, bytes: &[u8]) -> Result<ZExpr, ZDagError> {
+  decode_limited(link, bytes, &DecodeLimits::unlimited())
+}
+
+fn decode_limited(
+  link: &ZLink,
+  bytes: &[u8],
+  limits: &DecodeLimits,
+) -> Result<ZExpr, ZDagError> {
+  if !link.verify(bytes) {
+    return Err(ZDagError::Corrupt(*link));
+  }
+  match ZExpr::deserialize_limited(bytes, limits, true, None) {
+    Ok(([], x)) => Ok(x),
+    Ok((rest, _)) => Err(ZDagError::Malformed(*link, bytes.len() - rest.len())),
+    Err(Err::Error(ZExprDeserialError::LimitExceeded(_, e)))
+    | Err(Err::Failure(ZExprDeserialError::LimitExceeded(_, e))) => {
+      Err(ZDagError::LimitExceeded(e))
+    }
+    Err(Err::Error(e)) | Err(Err::Failure(e)) => {
+      Err(ZDagError::Malformed(*link, bytes.len() - e.rest().len()))
+    }
+    Err(Err::Incomplete(_)) => Err(ZDagError::Malformed(*link, bytes.len())),
+  }
+}
+
+/// Rebuild the whole tree under `x`, loading every block it links to. Each
+/// block is fetched once, however often it is linked.
+pub fn resolve<S: BlockSource + ?Sized>(
+  src: &S,
+  x: &ZExpr,
+) -> Result<ZExpr, ZDagError> {
+  resolve_limited(src, x, &DecodeLimits::unlimited())
+}
+
+/// Like `resolve`, but fails with `LimitExceeded` as soon as a block breaks
+/// `limits`, or the tree rebuilt so far is nested deeper or would serialize
+/// to more bytes than they allow.
+pub fn resolve_limited<S: BlockSource + ?Sized>(
+  src: &S,
+  x: &ZExpr,
+  limits: &DecodeLimits,
+) -> Result<ZExpr, ZDagError> {
+  let mut loaded: HashMap<ZLink, ZExpr> = HashMap::new();
+  let mut load_cached = |link: ZLink| -> Result<ZExpr, ZDagError> {
+    if let Some(x) = loaded.get(&link) {
+      return Ok(x.clone());
+    }
+    let x = load_limited(src, &link, limits)?;
+    loaded.insert(link, x.clone());
+    Ok(x)
+  };
+  // the serialized size of the tree rebuilt so far
+  let mut total: u64 = 0;
+  let mut count = |header: ZHeader, dat_len: usize| {
+    total = total
+      .saturating_add(header.encoded_len() as u64)
+      .saturating_add(dat_len as u64);
+    limits.check_total(total).map_err(ZDagError::LimitExceeded)
+  };
+  // the conses being rebuilt, with the elements still to visit
+  let mut stack: Vec<(Vec<ZExpr>, std::vec::IntoIter<ZExpr>)> = vec![];
+  let mut next = x.clone();
+  loop {
+    if let Some(link) = ZLink::from_zexpr(&next) {
+      next = load_cached(link)?;
+      continue;
+    }
+    match next {
+      ZExpr::Cons(ref mut xs) => {
+        let xs = std::mem::take(xs);
+        count(ZHeader::Cons(xs.len() as u64), 0)?;
+        stack.push((Vec::with_capacity(xs.len()), xs.into_iter()));
+        limits
+          .check_depth(stack.len())
+          .map_err(ZDagError::LimitExceeded)?;
+      }
+      ZExpr::Atom(typ, ref dat) => {
+        count(ZHeader::Atom(typ, dat.len() as u64), dat.len())?;
+        match stack.last_mut() {
+          None => return Ok(next),
+          Some((ys, _)) => ys.push(next),
+        }
+      }
+    }
+    next = loop {
+      let (_, rest) = stack.last_mut().unwrap();
+      if let Some(x) = rest.next() {
+        break x;
+      }
+      let (ys, _) = stack.pop().unwrap();
+      match stack.last_mut() {
+        None => return Ok(ZExpr::Cons(ys)),
+        Some((zs, _)) => zs.push(ZExpr::Cons(ys)),
+      }
+    };
+  }
+}
+
+/// Follow `path` down from `x`, taking the element at each index in turn and
+/// loading only the blocks on the way. The expression found is returned with
+/// its own links unresolved, unless it is itself a link.
+pub fn resolve_path<S: BlockSource + ?Sized>(
+  src: &S,
+  x: &ZExpr,
+  path: &[usize],
+) -> Result<ZExpr, ZDagError> {
+  resolve_path_limited(src, x, path, &DecodeLimits::unlimited())
+}
+
+/// Like `resolve_path`, but fails with `LimitExceeded` at the first block on
+/// the way which breaks `limits`.
+pub fn resolve_path_limited<S: BlockSource + ?Sized>(
+  src: &S,
+  x: &ZExpr,
+  path: &[usize],
+  limits: &DecodeLimits,
+) -> Result<ZExpr, ZDagError> {
+  let mut cur = x.clone();
+  for (n, &i) in path.iter().enumerate() {
+    if let Some(link) = ZLink::from_zexpr(&cur) {
+      cur = load_limited(src, &link, limits)?;
+    }
+    cur = match cur {
+      ZExpr::Cons(ref mut xs) if i < xs.len() => xs.swap_remove(i),
+      _ => return Err(ZDagError::BadPath(path[..=n].to_vec())),
+    };
+  }
+  match ZLink::from_zexpr(&cur) {
+    Some(link) => load_limited(src, &link, limits),
+    None => Ok(cur),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+  use std::cell::RefCell;
+
+  /// Records which blocks are fetched.
+  struct Counting<'a>(&'a BTreeMap<ZLink, Vec<u8>>, RefCell<Vec<ZLink>>);
+
+  impl<'a> BlockSource for Counting<'a> {
+    fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+      self.1.borrow_mut().push(*link);
+      self.0.get_block(link)
+    }
+  }
+
+  fn text(s: &str) -> ZExpr {
+    ZExpr::Atom(Text(None), s.as_bytes().to_vec())
+  }
+
+  fn doc() -> ZExpr {
+    let shared = ZExpr::Cons(vec![text("a long shared paragraph"); 4]);
+    ZExpr::Cons(vec![
+      text("title"),
+      ZExpr::Cons(vec![text("one"), shared.clone()]),
+      ZExpr::Cons(vec![text("two"), shared]),
+    ])
+  }
+
+  #[test]
+  fn zdag_split_resolve() {
+    let x = doc();
+    let (root, blocks) = split(&x, 100).unwrap();
+    // the two copies of the shared cons are stored once, and the root is
+    // small enough after it was cut out
+    assert_eq!(blocks.len(), 1);
+    match &root {
+      ZExpr::Cons(xs) => assert_eq!(xs.len(), 3),
+      y => panic!("unexpected {}", y),
+    }
+    assert!(root.encoded_len() < x.encoded_len());
+    assert_eq!(resolve(&blocks, &root).unwrap(), x);
+    // everything is a block with a small enough threshold
+    let (root, blocks) = split(&x, 0).unwrap();
+    assert!(ZLink::from_zexpr(&root).is_some());
+    assert_eq!(blocks.len(), 4);
+    assert_eq!(resolve(&blocks, &root).unwrap(), x);
+    // and nothing with a large one
+    let (root, blocks) = split(&x, usize::MAX).unwrap();
+    assert!(blocks.is_empty());
+    assert_eq!(root, x);
+    // bare digests aren't links, so they survive the trip
+    let sha = ZExpr::Cons(vec![
+      ZExpr::Atom(Hash(Some(32)), vec![7; 32]),
+      ZExpr::Atom(Sha256(Some(32)), vec![7; 32]),
+      ZExpr::Atom(Sha512(Some(64)), vec![7; 64]),
+    ]);
+    let (root, blocks) = split(&sha, 0).unwrap();
+    assert_eq!(resolve(&blocks, &root).unwrap(), sha);
+    // an atom shorter than its type can't be told from one of another type
+    let bad = ZExpr::Cons(vec![x, ZExpr::Atom(Nat(Some(8)), vec![1])]);
+    assert_eq!(split(&bad, 0).unwrap_err().path, vec![1]);
+  }
+
+  #[test]
+  fn zdag_resolve_path() {
+    let x = doc();
+    let (root, blocks) = split(&x, 0).unwrap();
+    let src = Counting(&blocks, RefCell::new(vec![]));
+    let y = resolve_path(&src, &root, &[1, 0]).unwrap();
+    assert_eq!(y, text("one"));
+    assert_eq!(src.1.borrow().len(), 2);
+    let y = resolve_path(&src, &root, &[2, 1]).unwrap();
+    assert_eq!(resolve(&blocks, &y).unwrap(), x_at(&x, &[2, 1]));
+    match resolve_path(&src, &root, &[1, 5]) {
+      Err(ZDagError::BadPath(p)) => assert_eq!(p, vec![1, 5]),
+      y => panic!("unexpected {:?}", y),
+    }
+    match resolve_path(&src, &root, &[0, 0]) {
+      Err(ZDagError::BadPath(p)) => assert_eq!(p, vec![0, 0]),
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
+  fn x_at(x: &ZExpr, path: &[usize]) -> ZExpr {
+    path.iter().fold(x.clone(), |x, &i| match &x {
+      ZExpr::Cons(xs) => xs[i].clone(),
+      _ => panic!("bad path"),
+    })
+  }
+
+  #[test]
+  fn zdag_bad_blocks() {
+    let (root, mut blocks) = split(&doc(), 0).unwrap();
+    let link = ZLink::from_zexpr(&root).unwrap();
+    let block = blocks.remove(&link).unwrap();
+    match resolve(&blocks, &root) {
+      Err(ZDagError::Missing(l)) => assert_eq!(l, link),
+      y => panic!("unexpected {:?}", y),
+    }
+    let mut corrupt = block.clone();
+    corrupt[1] ^= 1;
+    blocks.insert(link, corrupt);
+    match resolve(&blocks, &root) {
+      Err(ZDagError::Corrupt(l)) => assert_eq!(l, link),
+      y => panic!("unexpected {:?}", y),
+    }
+    // a block which hashes correctly but has trailing bytes
+    let mut trailing = block;
+    trailing.push(0);
+    let link = ZLink::of_bytes(&trailing);
+    blocks.insert(link, trailing.clone());
+    match load(&blocks, &link) {
+      Err(ZDagError::Malformed(l, pos)) => {
+        assert_eq!((l, pos), (link, trailing.len() - 1))
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
+  #[test]
+  fn zdag_resolve_limited() {
+    // each block links to the one before twice, so 64 small blocks resolve
+    // to 2^64 leaves
+    let mut blocks = BTreeMap::new();
+    let mut x = text("leaf");
+    for _ in 0..64 {
+      let bytes = ZExpr::Cons(vec![x.clone(), x]).serialize();
+      let link = ZLink::of_bytes(&bytes);
+      blocks.insert(link, bytes);
+      x = link.into();
+    }
+    let limits = DecodeLimits {
+      max_total_bytes: 1 << 16,
+      ..DecodeLimits::default()
+    };
+    match resolve_limited(&blocks, &x, &limits) {
+      Err(ZDagError::LimitExceeded(DecodeLimit::TotalBytes(n))) => {
+        assert!(n > 1 << 16 && n < 1 << 17)
+      }
+      y => panic!("unexpected {:?}", y.map(|_| ())),
+    }
+    let limits = DecodeLimits {
+      max_depth: 8,
+      ..DecodeLimits::default()
+    };
+    match resolve_limited(&blocks, &x, &limits) {
+      Err(ZDagError::LimitExceeded(DecodeLimit::Depth(9))) => {}
+      y => panic!("unexpected {:?}", y.map(|_| ())),
+    }
+    // a tree within the limits resolves as before
+    let (root, blocks) = split(&doc(), 0).unwrap();
+    let limits = DecodeLimits::default();
+    assert_eq!(resolve_limited(&blocks, &root, &limits).unwrap(), doc());
+    // and the blocks on a path are decoded under them
+    let limits = DecodeLimits {
+      max_cons_len: 2,
+      ..DecodeLimits::default()
+    };
+    match resolve_path_limited(&blocks, &root, &[1, 0], &limits) {
+      Err(ZDagError::LimitExceeded(DecodeLimit::ConsArity(3))) => {}
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
+  #[quickcheck]
+  fn zdag_split_resolve_any(x: ZExpr, threshold: u8) -> bool {
+    let (root, blocks) = split(&x, threshold as usize).unwrap();
+    resolve(&blocks, &root).is_ok_and(|y| y == x)
+  }
+}
@@ -0,0 +1,287 @@
+//! Mark-and-sweep garbage collection for a `BlockStore`.
+//!
+//! Marking starts from a set of root links, loads each reachable block and
+//! follows every link atom inside it. Sweeping then removes the stored blocks
+//! which weren't marked. A block another process puts into an `FsStore` while
+//! a collection runs survives it, even if it was stored, unreachable, before:
+//! blocks first stored during the collection aren't listed for sweeping, and
+//! `BlockStore::sweep` spares the rest. So a writer which puts every block of
+//! a new tree, including the ones already stored, keeps the whole tree.
+
+use std::collections::BTreeSet;
+
+use crate::zdag;
+use crate::zdag::ZDagError;
+use crate::zlink::ZLink;
+use crate::zstore::BlockStore;
+use crate::ZExpr;
+
+/// What a collection found, or would remove on a dry run.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct GcReport {
+  /// Stored blocks reachable from the roots
+  pub live: BTreeSet<ZLink>,
+  /// Blocks reachable from the roots which aren't in the store
+  pub missing: BTreeSet<ZLink>,
+  /// Stored blocks not reachable from the roots, which a collection removes
+  pub garbage: BTreeSet<ZLink>,
+}
+
+/// Find the blocks reachable from `roots`. A reachable block which fails its
+/// hash check or can't be decoded stops the walk with an error, since the
+/// blocks it links to can't be known.
+pub fn mark<S: BlockStore + ?Sized>(
+  store: &S,
+  roots: &[ZLink],
+) -> Result<GcReport, ZDagError> {
+  let mut report = GcReport::default();
+  // listed before walking, so a block put while marking isn't garbage
+  let listed = store.list()?;
+  let mut todo: Vec<ZLink> = roots.to_vec();
+  while let Some(link) = todo.pop() {
+    if report.live.contains(&link) || report.missing.contains(&link) {
+      continue;
+    }
+    let block = match zdag::load(store, &link) {
+      Ok(block) => block,
+      Err(ZDagError::Missing(_)) => {
+        report.missing.insert(link);
+        continue;
+      }
+      Err(e) => return Err(e),
+    };
+    report.live.insert(link);
+    let mut stack = vec![&block];
+    while let Some(x) = stack.pop() {
+      match x {
+        ZExpr::Cons(xs) => stack.extend(xs.iter()),
+        atom => todo.extend(ZLink::from_zexpr(atom)),
+      }
+    }
+  }
+  for link in listed {
+    if !report.live.contains(&link) {
+      report.garbage.insert(link);
+    }
+  }
+  Ok(report)
+}
+
+/// Report what `collect` would remove, without removing anything.
+pub fn dry_run<S: BlockStore + ?Sized>(
+  store: &S,
+  roots: &[ZLink],
+) -> Result<GcReport, ZDagError> {
+  mark(store, roots)
+}
+
+/// Remove every block not reachable from `roots` which isn't put again while
+/// collecting, returning the report of what was removed.
+pub fn collect<S: BlockStore + ?Sized>(
+  store: &mut S,
+  roots: &[ZLink],
+) -> Result<GcReport, ZDagError> {
+  store.begin_sweep()?;
+  let report = mark_and_sweep(store, roots);
+  let ended = store.end_sweep();
+  let report = report?;
+  ended?;
+  Ok(report)
+}
+
+fn mark_and_sweep<S: BlockStore + ?Sized>(
+  store: &mut S,
+  roots: &[ZLink],
+) -> Result<GcReport, ZDagError> {
+  let mut report = mark(store, roots)?;
+  for link in std::mem::take(&mut report.garbage) {
+    if store.sweep(&link)? {
+      report.garbage.insert(link);
+    }
+  }
+  Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zdag::BlockSource;
+  use crate::zlink::HashAlg;
+  use crate::zstore::{FsStore, MemoryStore};
+  use crate::ztype::ZType::*;
+  use std::cell::RefCell;
+
+  fn text(s: &str) -> ZExpr {
+    ZExpr::Atom(Text(None), s.as_bytes().to_vec())
+  }
+
+  /// Stores `x` split into blocks, returning its root link.
+  fn put_tree<S: BlockStore>(store: &mut S, x: &ZExpr) -> ZLink {
+    let (root, blocks) = zdag::split(x, 0).unwrap();
+    for bytes in blocks.values() {
+      store.put(bytes).unwrap();
+    }
+    ZLink::from_zexpr(&root).unwrap()
+  }
+
+  fn exercise<S: BlockStore>(store: &mut S) {
+    let shared = ZExpr::Cons(vec![text("shared")]);
+    let a = ZExpr::Cons(vec![text("a"), shared.clone()]);
+    let b = ZExpr::Cons(vec![text("b"), shared]);
+    let (x, a) = (a.clone(), put_tree(store, &a));
+    let b = put_tree(store, &b);
+    assert_eq!(store.list().unwrap().len(), 3);
+
+    let report = dry_run(store, &[a]).unwrap();
+    assert_eq!(report.live.len(), 2);
+    assert!(report.missing.is_empty());
+    assert_eq!(report.garbage.iter().collect::<Vec<_>>(), vec![&b]);
+    // a dry run removes nothing
+    assert_eq!(store.list().unwrap().len(), 3);
+
+    assert_eq!(collect(store, &[a]).unwrap(), report);
+    assert_eq!(
+      store.list().unwrap(),
+      report.live.iter().copied().collect::<Vec<_>>()
+    );
+    assert_eq!(zdag::resolve(store, &a.into()).unwrap(), x);
+
+    // roots which aren't stored are reported, not errors
+    let report = collect(store, &[a, b]).unwrap();
+    assert_eq!(report.missing.iter().collect::<Vec<_>>(), vec![&b]);
+    assert!(report.garbage.is_empty());
+    // and with no roots everything goes
+    collect(store, &[]).unwrap();
+    assert!(store.list().unwrap().is_empty());
+  }
+
+  #[test]
+  fn zgc_memory() {
+    exercise(&mut MemoryStore::new());
+  }
+
+  #[test]
+  fn zgc_fs() {
+    let dir = tempfile::tempdir().unwrap();
+    exercise(&mut FsStore::open(dir.path()).unwrap());
+  }
+
+  #[test]
+  fn zgc_corrupt_live_block() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = FsStore::open(dir.path()).unwrap();
+    let x = ZExpr::Cons(vec![text("x"), ZExpr::Cons(vec![text("y")])]);
+    let root = put_tree(&mut store, &x);
+    std::fs::write(store.path(&root), b"tampered").unwrap();
+    match collect(&mut store, &[root]) {
+      Err(ZDagError::Corrupt(l)) => assert_eq!(l, root),
+      y => panic!("unexpected {:?}", y),
+    }
+    // nothing was removed
+    assert_eq!(store.list().unwrap().len(), 2);
+  }
+
+  /// An `FsStore` which, on its first read, has another handle on the same
+  /// directory put a block, as a concurrent writer would.
+  struct Racing {
+    store: FsStore,
+    other: RefCell<Option<(FsStore, Vec<u8>)>>,
+  }
+
+  impl BlockSource for Racing {
+    fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+      if let Some((mut other, bytes)) = self.other.borrow_mut().take() {
+        other.put(&bytes)?;
+      }
+      self.store.get_block(link)
+    }
+  }
+
+  impl BlockStore for Racing {
+    fn put_with(
+      &mut self,
+      alg: HashAlg,
+      bytes: &[u8],
+    ) -> Result<ZLink, ZDagError> {
+      self.store.put_with(alg, bytes)
+    }
+
+    fn get(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+      self.get_block(link)
+    }
+
+    fn has(&self, link: &ZLink) -> Result<bool, ZDagError> {
+      self.store.has(link)
+    }
+
+    fn remove(&mut self, link: &ZLink) -> Result<bool, ZDagError> {
+      self.store.remove(link)
+    }
+
+    fn list(&self) -> Result<Vec<ZLink>, ZDagError> {
+      self.store.list()
+    }
+
+    fn begin_sweep(&mut self) -> Result<(), ZDagError> {
+      self.store.begin_sweep()
+    }
+
+    fn sweep(&mut self, link: &ZLink) -> Result<bool, ZDagError> {
+      self.store.sweep(link)
+    }
+
+    fn end_sweep(&mut self) -> Result<(), ZDagError> {
+      self.store.end_sweep()
+    }
+  }
+
+  #[test]
+  fn zgc_put_while_marking() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = FsStore::open(dir.path()).unwrap();
+    let root = put_tree(&mut store, &ZExpr::Cons(vec![text("root")]));
+    let old = put_tree(&mut store, &ZExpr::Cons(vec![text("old")]));
+    let late = ZExpr::Cons(vec![text("late")]).serialize();
+    let other = FsStore::open(dir.path()).unwrap();
+    let mut store = Racing {
+      store,
+      other: RefCell::new(Some((other, late.clone()))),
+    };
+    let report = collect(&mut store, &[root]).unwrap();
+    assert_eq!(report.garbage.iter().collect::<Vec<_>>(), vec![&old]);
+    // the block put during the collection survives it
+    let mut expected = vec![root, ZLink::of_bytes(&late)];
+    expected.sort();
+    assert_eq!(store.list().unwrap(), expected);
+  }
+
+  #[test]
+  fn zgc_put_again_while_marking() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = FsStore::open(dir.path()).unwrap();
+    let root = put_tree(&mut store, &ZExpr::Cons(vec![text("root")]));
+    let old = ZExpr::Cons(vec![text("old")]).serialize();
+    let old = store.put(&old).map(|link| (link, old)).unwrap();
+    // an ingester puts the unreachable block again, then a root linking to
+    // it, while the collection is marking
+    let other = FsStore::open(dir.path()).unwrap();
+    let mut store = Racing {
+      store,
+      other: RefCell::new(Some((other, old.1.clone()))),
+    };
+    let report = collect(&mut store, &[root]).unwrap();
+    assert!(report.garbage.is_empty());
+    let new_root = ZExpr::Cons(vec![text("new"), old.0.into()]);
+    let new_root = store.put_expr(&new_root).unwrap();
+    assert_eq!(store.get(&old.0).unwrap(), Some(old.1));
+    assert!(zdag::resolve(&store, &new_root.into()).is_ok());
+    // once nothing is being put, the next collection removes it
+    let report = collect(&mut store, &[root]).unwrap();
+    let mut garbage = vec![old.0, new_root];
+    garbage.sort();
+    assert_eq!(report.garbage.into_iter().collect::<Vec<_>>(), garbage);
+    assert_eq!(store.list().unwrap(), vec![root]);
+    // and leaves no pins behind
+    assert!(!dir.path().join(".gc").exists());
+  }
+}
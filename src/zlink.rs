@@ -0,0 +1,371 @@
+use core::fmt;
+use nom::branch::alt;
+use nom::bytes::complete::tag;
+use nom::combinator::{opt, value};
+use nom::sequence::terminated;
+use nom::Err;
+use nom::IResult;
+use sha2::Digest;
+
+use crate::zbase;
+use crate::zbase::ZBase;
+use crate::ztype::ZType;
+use crate::zvalid::Invalid;
+use crate::ZExpr;
+use crate::ZExprError;
+
+/// The hash function behind a link, each with its own `ZType` for digests.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Default)]
+pub enum HashAlg {
+  /// `hash256`
+  #[default]
+  Blake3,
+  /// `sha256256`
+  Sha256,
+  /// `sha512512`
+  Sha512,
+}
+
+impl HashAlg {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Self::Blake3 => "blake3",
+      Self::Sha256 => "sha256",
+      Self::Sha512 => "sha512",
+    }
+  }
+
+  /// The length of a digest in bytes.
+  pub fn digest_len(&self) -> usize {
+    match self {
+      Self::Blake3 | Self::Sha256 => 32,
+      Self::Sha512 => 64,
+    }
+  }
+
+  /// The type of an atom holding a digest. A link names its algorithm by
+  /// this type's code; see `ZLink`.
+  pub fn ztype(&self) -> ZType {
+    let len = Some(self.digest_len() as u64);
+    match self {
+      Self::Blake3 => ZType::Hash(len),
+      Self::Sha256 => ZType::Sha256(len),
+      Self::Sha512 => ZType::Sha512(len),
+    }
+  }
+
+  /// The algorithm whose digests are atoms of type `typ`.
+  pub fn from_ztype(typ: ZType) -> Option<Self> {
+    [Self::Blake3, Self::Sha256, Self::Sha512]
+      .iter()
+      .copied()
+      .find(|alg| alg.ztype() == typ)
+  }
+
+  /// The algorithm whose digest type has the code `code`, as a `link` atom
+  /// names it.
+  pub fn from_code(code: u8) -> Option<Self> {
+    [Self::Blake3, Self::Sha256, Self::Sha512]
+      .iter()
+      .copied()
+      .find(|alg| alg.ztype().code() == u64::from(code))
+  }
+
+  fn hasher(&self) -> Hasher {
+    match self {
+      Self::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+      Self::Sha256 => Hasher::Sha256(sha2::Sha256::new()),
+      Self::Sha512 => Hasher::Sha512(sha2::Sha512::new()),
+    }
+  }
+}
+
+enum Hasher {
+  Blake3(Box<blake3::Hasher>),
+  Sha256(sha2::Sha256),
+  Sha512(sha2::Sha512),
+}
+
+impl Hasher {
+  fn finish(self, alg: HashAlg) -> ZLink {
+    let mut digest = [0u8; 64];
+    match self {
+      Self::Blake3(h) => digest[..32].copy_from_slice(h.finalize().as_bytes()),
+      Self::Sha256(h) => digest[..32].copy_from_slice(&h.finalize()),
+      Self::Sha512(h) => digest.copy_from_slice(&h.finalize()),
+    }
+    ZLink { alg, digest }
+  }
+}
+
+impl std::io::Write for Hasher {
+  fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+    match self {
+      Self::Blake3(h) => {
+        h.update(buf);
+      }
+      Self::Sha256(h) => h.update(buf),
+      Self::Sha512(h) => h.update(buf),
+    }
+    Ok(buf.len())
+  }
+
+  fn flush(&mut self) -> std::io::Result<()> {
+    Ok(())
+  }
+}
+
+/// A content identifier for a `ZExpr`: a digest of its serialized bytes,
+/// blake3 unless another `HashAlg` is asked for. As a `ZExpr` it is a `link`
+/// atom holding the code of its algorithm's digest type and then the digest,
+/// so that atoms holding only a digest, e.g. `hash256`, aren't links. As text
+/// it is the digest in any `ZBase` after a `#`, with the algorithm's name in
+/// between unless it is blake3, e.g. `#vyyyy...` or `#sha256:vyyyy...`.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ZLink {
+  alg: HashAlg,
+  /// The digest, padded with zeros past the algorithm's digest length
+  digest: [u8; 64],
+}
+
+impl ZLink {
+  /// A blake3 link.
+  pub fn new(digest: [u8; 32]) -> Self {
+    let mut padded = [0u8; 64];
+    padded[..32].copy_from_slice(&digest);
+    ZLink {
+      alg: HashAlg::Blake3,
+      digest: padded,
+    }
+  }
+
+  /// The blake3 link of `x`, hashing its serialization as it is written.
+  /// Fails if a sized atom in `x` has data of another length, since it would
+  /// share its serialization, and so its link, with another expression. See
+  /// `ZExpr::check_lengths`.
+  pub fn of(x: &ZExpr) -> Result<Self, Invalid> {
+    ZLink::of_with(HashAlg::Blake3, x)
+  }
+
+  pub fn of_with(alg: HashAlg, x: &ZExpr) -> Result<Self, Invalid> {
+    x.check_lengths()?;
+    let mut hasher = alg.hasher();
+    x.serialize_into(&mut hasher)
+      .expect("writing to a hasher can't fail");
+    Ok(hasher.finish(alg))
+  }
+
+  /// The blake3 link of an already serialized expression.
+  pub fn of_bytes(bytes: &[u8]) -> Self {
+    ZLink::of_bytes_with(HashAlg::Blake3, bytes)
+  }
+
+  pub fn of_bytes_with(alg: HashAlg, bytes: &[u8]) -> Self {
+    let mut hasher = alg.hasher();
+    std::io::Write::write_all(&mut hasher, bytes)
+      .expect("writing to a hasher can't fail");
+    hasher.finish(alg)
+  }
+
+  /// Whether `bytes` hash to this link, under the link's own algorithm.
+  pub fn verify(&self, bytes: &[u8]) -> bool {
+    ZLink::of_bytes_with(self.alg, bytes) == *self
+  }
+
+  /// A blake3 link.
+  pub fn from_bytes(digest: &[u8]) -> Option<Self> {
+    ZLink::from_bytes_with(HashAlg::Blake3, digest)
+  }
+
+  pub fn from_bytes_with(alg: HashAlg, digest: &[u8]) -> Option<Self> {
+    if digest.len() != alg.digest_len() {
+      return None;
+    }
+    let mut padded = [0u8; 64];
+    padded[..digest.len()].copy_from_slice(digest);
+    Some(ZLink {
+      alg,
+      digest: padded,
+    })
+  }
+
+  pub fn alg(&self) -> HashAlg {
+    self.alg
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    &self.digest[..self.alg.digest_len()]
+  }
+
+  /// The link held by an atom written by `to_zexpr`.
+  pub fn from_zexpr(x: &ZExpr) -> Option<Self> {
+    match x {
+      ZExpr::Atom(ZType::Link(_), dat) => ZLink::from_link_data(dat),
+      _ => None,
+    }
+  }
+
+  /// The link held by the data of a `link` atom.
+  pub(crate) fn from_link_data(dat: &[u8]) -> Option<Self> {
+    let (&code, digest) = dat.split_first()?;
+    ZLink::from_bytes_with(HashAlg::from_code(code)?, digest)
+  }
+
+  pub fn to_zexpr(&self) -> ZExpr {
+    let mut dat = Vec::with_capacity(1 + self.alg.digest_len());
+    dat.push(self.alg.ztype().code() as u8);
+    dat.extend_from_slice(self.as_bytes());
+    ZExpr::Atom(ZType::Link(None), dat)
+  }
+}
+
+impl From<ZLink> for ZExpr {
+  fn from(x: ZLink) -> Self {
+    x.to_zexpr()
+  }
+}
+
+impl fmt::Display for ZLink {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "#")?;
+    if self.alg != HashAlg::Blake3 {
+      write!(f, "{}:", self.alg.name())?;
+    }
+    write!(f, "{}", zbase::encode(ZBase::Z32, self.as_bytes()))
+  }
+}
+
+impl fmt::Debug for ZLink {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "ZLink({})", self)
+  }
+}
+
+// #<bytes> or #<alg>:<bytes>
+pub fn parse(i: &str) -> IResult<&str, ZLink, ZExprError<&str>> {
+  let (i, _) = tag("#")(i)?;
+  let (i, alg) = opt(terminated(
+    alt((
+      value(HashAlg::Blake3, tag("blake3")),
+      value(HashAlg::Sha256, tag("sha256")),
+      value(HashAlg::Sha512, tag("sha512")),
+    )),
+    tag(":"),
+  ))(i)?;
+  let alg = alg.unwrap_or_default();
+  let (rest, (_, digest)) = zbase::parse(i).map_err(Err::convert)?;
+  match ZLink::from_bytes_with(alg, &digest) {
+    Some(link) => Ok((rest, link)),
+    None => Err(Err::Error(ZExprError::LinkLength(i, digest.len()))),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+  use crate::zvalid::Violation;
+
+  #[test]
+  fn zlink_of() {
+    let x = ZExpr::Cons(vec![ZExpr::Atom(Text(None), b"hello".to_vec())]);
+    let link = ZLink::of(&x).unwrap();
+    assert_eq!(link, ZLink::of_bytes(&x.serialize()));
+    assert_eq!(link.as_bytes(), blake3::hash(&x.serialize()).as_bytes());
+    assert_eq!(x.link(), Ok(link.to_zexpr()));
+    assert_eq!(ZLink::from_zexpr(&x.link().unwrap()), Some(link));
+    assert_ne!(link, ZLink::of(&ZExpr::Cons(vec![x])).unwrap());
+    // these would serialize alike, so neither has a link
+    let short = ZExpr::Atom(Nat(Some(2)), vec![1, 2, 3]);
+    let long = ZExpr::Atom(Nat(Some(3)), vec![1, 2, 3]);
+    assert_eq!(short.serialize(), long.serialize());
+    assert_eq!(
+      ZLink::of(&ZExpr::Cons(vec![short])),
+      Err(Invalid {
+        path: vec![0],
+        violation: Violation::Length(2, 3)
+      })
+    );
+    assert!(ZLink::of(&long).is_ok());
+  }
+
+  #[test]
+  fn zlink_parse() {
+    let link = ZLink::of(&ZExpr::Cons(vec![])).unwrap();
+    let text = format!("{}", link);
+    assert!(text.starts_with("#v"));
+    assert_eq!(parse(&text), Ok(("", link)));
+    let hex = format!("#{}", zbase::encode(ZBase::Z16, link.as_bytes()));
+    assert_eq!(parse(&hex), Ok(("", link)));
+    match parse("#xdead") {
+      Err(Err::Error(ZExprError::LinkLength(_, 2))) => {}
+      y => panic!("unexpected {:?}", y),
+    }
+    // links print and parse as part of an expression
+    let x = ZExpr::Cons(vec![link.to_zexpr(), ZExpr::Cons(vec![])]);
+    let text = format!("{}", x);
+    assert_eq!(text, format!("({} ())", link));
+    assert_eq!(crate::parse(&text), Ok(("", x)));
+  }
+
+  #[test]
+  fn zlink_algs() {
+    let x = ZExpr::Cons(vec![ZExpr::Atom(Text(None), b"hello".to_vec())]);
+    let bytes = x.serialize();
+    let sha256 = ZLink::of_with(HashAlg::Sha256, &x).unwrap();
+    let sha512 = ZLink::of_with(HashAlg::Sha512, &x).unwrap();
+    assert_eq!(sha256.as_bytes(), &sha2::Sha256::digest(&bytes)[..]);
+    assert_eq!(sha512.as_bytes(), &sha2::Sha512::digest(&bytes)[..]);
+    assert_eq!(sha512, ZLink::of_bytes_with(HashAlg::Sha512, &bytes));
+    for link in &[ZLink::of(&x).unwrap(), sha256, sha512] {
+      assert!(link.verify(&bytes));
+      assert!(!link.verify(b"other"));
+      assert_eq!(ZLink::from_zexpr(&link.to_zexpr()), Some(*link));
+      let text = format!("{}", link);
+      assert_eq!(parse(&text), Ok(("", *link)));
+    }
+    // the same digest under another algorithm is another link
+    let blake3 = ZLink::from_bytes(sha256.as_bytes()).unwrap();
+    assert_ne!(blake3, sha256);
+    assert!(!blake3.verify(&bytes));
+    assert_eq!(
+      ZLink::from_bytes_with(HashAlg::Sha512, sha256.as_bytes()),
+      None
+    );
+    // every link is a link atom, while a bare digest is just data
+    let blake3 = ZLink::of(&x).unwrap();
+    let dat = [&[0x07][..], blake3.as_bytes()].concat();
+    assert_eq!(blake3.to_zexpr(), ZExpr::Atom(Link(None), dat));
+    let dat = [&[0x08][..], sha256.as_bytes()].concat();
+    assert_eq!(sha256.to_zexpr(), ZExpr::Atom(Link(None), dat));
+    let digest = ZExpr::Atom(Hash(Some(32)), blake3.as_bytes().to_vec());
+    assert_eq!(ZLink::from_zexpr(&digest), None);
+    let digest = ZExpr::Atom(Sha256(Some(32)), sha256.as_bytes().to_vec());
+    assert_eq!(ZLink::from_zexpr(&digest), None);
+    assert_eq!(format!("{}", digest).rsplit(':').next(), Some("sha256256"));
+    let digest = ZExpr::Atom(Sha512(Some(64)), sha512.as_bytes().to_vec());
+    assert_eq!(ZLink::from_zexpr(&digest), None);
+    // a digest of the wrong length, or an unknown algorithm, isn't a link
+    let dat = [&[0x09][..], sha256.as_bytes()].concat();
+    assert_eq!(ZLink::from_zexpr(&ZExpr::Atom(Link(None), dat)), None);
+    let dat = [&[0x05][..], sha256.as_bytes()].concat();
+    assert_eq!(ZLink::from_zexpr(&ZExpr::Atom(Link(None), dat)), None);
+
+    let text = format!("{}", sha256);
+    assert!(text.starts_with("#sha256:v"));
+    assert!(format!("{}", sha512).starts_with("#sha512:v"));
+    let link = ZLink::of(&x).unwrap();
+    assert_eq!(
+      parse(&format!("#blake3:{}", &format!("{}", link)[1..])),
+      Ok(("", link))
+    );
+    match parse("#sha512:xdead") {
+      Err(Err::Error(ZExprError::LinkLength(_, 2))) => {}
+      y => panic!("unexpected {:?}", y),
+    }
+    let x = ZExpr::Cons(vec![sha256.to_zexpr(), sha512.to_zexpr()]);
+    let text = format!("{}", x);
+    assert_eq!(text, format!("({} {})", sha256, sha512));
+    assert_eq!(crate::parse(&text), Ok(("", x)));
+  }
+}
@@ -0,0 +1,821 @@
+//! Merkle digests of `ZExpr` trees, and proofs that a subtree sits at a given
+//! path under a digest.
+//!
+//! A Merkle digest is its own blake3 scheme, not a `ZLink`: a link commits to
+//! the exact bytes of a block, while a Merkle digest can be checked, and
+//! updated by `HashedZExpr`, one level at a time. The two are tied together
+//! by a `MerkleRoot` block, which holds a tree's digest and its link. A
+//! client holding the link of that block fetches only the block itself, and
+//! checks proofs against the digest in it with `MerkleProof::verify_link`.
+//!
+//! Every hash input starts with a byte saying what it is:
+//!
+//! - an atom is `0x00`, its type code and declared length, then its data;
+//! - a cons is `0x01` and its length, then, if it isn't empty, the root of
+//!   the binary tree over its elements' digests;
+//! - a node of that tree is `0x02` and its two children.
+//!
+//! The tree over `n` digests is the one RFC 6962 uses: its left subtree holds
+//! the largest power of two less than `n` of them. So the position of an
+//! element in a cons of width `n` is proved by about `log2(n)` digests.
+
+use std::convert::TryFrom;
+
+use crate::zdag::decode_block;
+use crate::zlink::ZLink;
+use crate::ztype::ZType;
+use crate::zvalid::Invalid;
+use crate::ZExpr;
+
+/// The Merkle digest of a tree, or of a node of the tree over a cons's
+/// elements.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug)]
+pub struct MerkleDigest(pub [u8; 32]);
+
+impl MerkleDigest {
+  pub fn as_bytes(&self) -> &[u8; 32] {
+    &self.0
+  }
+
+  /// The digest as a `bytes256` atom. It's deliberately not a link atom, so
+  /// nothing which follows links tries to fetch it.
+  pub fn to_zexpr(&self) -> ZExpr {
+    ZExpr::Atom(ZType::Bytes(Some(32)), self.0.to_vec())
+  }
+
+  /// Read back a digest written by `to_zexpr`.
+  pub fn from_zexpr(x: &ZExpr) -> Option<Self> {
+    match x {
+      ZExpr::Atom(ZType::Bytes(Some(32)), dat) => {
+        <[u8; 32]>::try_from(dat.as_slice()).ok().map(MerkleDigest)
+      }
+      _ => None,
+    }
+  }
+}
+
+fn hash(parts: &[&[u8]]) -> MerkleDigest {
+  let mut hasher = blake3::Hasher::new();
+  for part in parts {
+    hasher.update(part);
+  }
+  MerkleDigest(*hasher.finalize().as_bytes())
+}
+
+/// The digest of an atom. The declared length is hashed apart from the data,
+/// so atoms whose data doesn't have their declared length can't collide.
+pub fn atom_digest(typ: ZType, dat: &[u8]) -> MerkleDigest {
+  let len = match typ.length() {
+    None => [0; 9],
+    Some(len) => {
+      let mut bytes = [1; 9];
+      bytes[1..].copy_from_slice(&len.to_be_bytes());
+      bytes
+    }
+  };
+  hash(&[&[0x00], &typ.code().to_be_bytes(), &len, dat])
+}
+
+/// The digest of a cons whose elements have the digests `xs`.
+pub fn cons_digest(xs: &[MerkleDigest]) -> MerkleDigest {
+  let len = (xs.len() as u64).to_be_bytes();
+  match tree_root(xs) {
+    None => hash(&[&[0x01], &len]),
+    Some(root) => hash(&[&[0x01], &len, &root.0]),
+  }
+}
+
+fn tree_node(l: &MerkleDigest, r: &MerkleDigest) -> MerkleDigest {
+  hash(&[&[0x02], &l.0, &r.0])
+}
+
+/// The root of the tree over `xs`. Pairing neighbours level by level, with an
+/// odd one out carried up as it is, builds the same tree as splitting at the
+/// largest power of two.
+fn tree_root(xs: &[MerkleDigest]) -> Option<MerkleDigest> {
+  let mut level = xs.to_vec();
+  while level.len() > 1 {
+    level = level
+      .chunks(2)
+      .map(|pair| match pair {
+        [l, r] => tree_node(l, r),
+        [x] => *x,
+        _ => unreachable!(),
+      })
+      .collect();
+  }
+  level.pop()
+}
+
+/// The digests proving where `xs[index]` sits in the tree over `xs`, from the
+/// bottom of the tree up.
+fn tree_path(xs: &[MerkleDigest], index: usize) -> Vec<MerkleDigest> {
+  let mut path = vec![];
+  let (mut lo, mut hi, mut i) = (0, xs.len(), index);
+  while hi - lo > 1 {
+    let mut k = 1;
+    while k * 2 < hi - lo {
+      k *= 2;
+    }
+    if i < k {
+      path.extend(tree_root(&xs[lo + k..hi]));
+      hi = lo + k;
+    } else {
+      path.extend(tree_root(&xs[lo..lo + k]));
+      lo += k;
+      i -= k;
+    }
+  }
+  path.reverse();
+  path
+}
+
+/// The root of a tree of `width` digests which has `leaf` at `index` and
+/// `path` as its proof, or `None` if `path` can't be such a proof. This is
+/// the inclusion proof check of RFC 9162, section 2.1.3.2.
+fn tree_climb(
+  index: usize,
+  width: usize,
+  leaf: MerkleDigest,
+  path: &[MerkleDigest],
+) -> Option<MerkleDigest> {
+  if index >= width {
+    return None;
+  }
+  let (mut f, mut s, mut r) = (index, width - 1, leaf);
+  for p in path {
+    if s == 0 {
+      return None;
+    }
+    if f % 2 == 1 || f == s {
+      r = tree_node(p, &r);
+      while f % 2 == 0 && f != 0 {
+        f >>= 1;
+        s >>= 1;
+      }
+    } else {
+      r = tree_node(&r, p);
+    }
+    f >>= 1;
+    s >>= 1;
+  }
+  if s == 0 {
+    Some(r)
+  } else {
+    None
+  }
+}
+
+/// The Merkle digest of `x`.
+pub fn digest(x: &ZExpr) -> MerkleDigest {
+  // the conses being hashed, with the digests of the elements visited so far
+  let mut stack: Vec<(Vec<MerkleDigest>, std::slice::Iter<ZExpr>)> = vec![];
+  let mut next = x;
+  loop {
+    match next {
+      ZExpr::Atom(typ, dat) => {
+        let d = atom_digest(*typ, dat);
+        match stack.last_mut() {
+          None => return d,
+          Some((ds, _)) => ds.push(d),
+        }
+      }
+      ZExpr::Cons(xs) => stack.push((Vec::with_capacity(xs.len()), xs.iter())),
+    }
+    next = loop {
+      let (_, rest) = stack.last_mut().unwrap();
+      if let Some(x) = rest.next() {
+        break x;
+      }
+      let (ds, _) = stack.pop().unwrap();
+      let d = cons_digest(&ds);
+      match stack.last_mut() {
+        None => return d,
+        Some((es, _)) => es.push(d),
+      }
+    };
+  }
+}
+
+/// Evidence that some subtree sits at `path` under a Merkle digest: for each
+/// cons along the path, its width and the digests proving where the path's
+/// element sits in the tree over its elements.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct MerkleProof {
+  pub path: Vec<usize>,
+  /// The width of the cons at each step of the path, from the root down
+  pub widths: Vec<usize>,
+  /// The tree proof at each step of the path, from the root down
+  pub siblings: Vec<Vec<MerkleDigest>>,
+}
+
+impl MerkleProof {
+  /// Prove the position of the subtree of `x` at `path`, or `None` if there is
+  /// no such subtree.
+  /// Every digest is computed once, so to prove several paths into the same
+  /// tree, build a `HashedZExpr` and use its `proof`.
+  pub fn new(x: &ZExpr, path: &[usize]) -> Option<Self> {
+    HashedZExpr::new(x).proof(path)
+  }
+
+  /// The root digest this proof leads to from a subtree with digest `leaf`,
+  /// or `None` if the proof is malformed.
+  pub fn root(&self, leaf: MerkleDigest) -> Option<MerkleDigest> {
+    if self.path.len() != self.widths.len()
+      || self.path.len() != self.siblings.len()
+    {
+      return None;
+    }
+    let steps = self.path.iter().zip(&self.widths).zip(&self.siblings);
+    let mut d = leaf;
+    for ((&i, &width), ds) in steps.rev() {
+      let root = tree_climb(i, width, d, ds)?;
+      d = hash(&[&[0x01], &(width as u64).to_be_bytes(), &root.0]);
+    }
+    Some(d)
+  }
+
+  /// Check that `leaf` sits at the proof's path under `root`.
+  pub fn verify(&self, root: &MerkleDigest, leaf: &ZExpr) -> bool {
+    self.verify_digest(root, digest(leaf))
+  }
+
+  /// Check that a subtree with digest `leaf` sits at the proof's path under
+  /// `root`, for when only the subtree's digest is known.
+  pub fn verify_digest(&self, root: &MerkleDigest, leaf: MerkleDigest) -> bool {
+    self.root(leaf) == Some(*root)
+  }
+
+  /// Check that `leaf` sits at the proof's path under the tree of the
+  /// `MerkleRoot` block whose link is `root`, given that block's bytes.
+  pub fn verify_link(&self, root: &ZLink, block: &[u8], leaf: &ZExpr) -> bool {
+    match decode_block(root, block)
+      .ok()
+      .as_ref()
+      .and_then(MerkleRoot::from_zexpr)
+    {
+      Some(r) => self.verify(&r.digest, leaf),
+      None => false,
+    }
+  }
+
+  /// The proof as `((<index:nat64> ...) (<width:nat64> ...)
+  /// ((<digest:bytes256> ...) ...))`, for sending.
+  pub fn to_zexpr(&self) -> ZExpr {
+    let nat = |i: &usize| {
+      ZExpr::Atom(ZType::Nat(Some(8)), (*i as u64).to_be_bytes().to_vec())
+    };
+    let siblings = self
+      .siblings
+      .iter()
+      .map(|ds| ZExpr::Cons(ds.iter().map(MerkleDigest::to_zexpr).collect()))
+      .collect();
+    ZExpr::Cons(vec![
+      ZExpr::Cons(self.path.iter().map(nat).collect()),
+      ZExpr::Cons(self.widths.iter().map(nat).collect()),
+      ZExpr::Cons(siblings),
+    ])
+  }
+
+  /// Read back a proof written by `to_zexpr`.
+  pub fn from_zexpr(x: &ZExpr) -> Option<Self> {
+    let (path, widths, siblings) = match x {
+      ZExpr::Cons(xs) => match xs.as_slice() {
+        [ZExpr::Cons(path), ZExpr::Cons(widths), ZExpr::Cons(siblings)] => {
+          (path, widths, siblings)
+        }
+        _ => return None,
+      },
+      _ => return None,
+    };
+    let nat = |i: &ZExpr| match i {
+      ZExpr::Atom(ZType::Nat(Some(8)), dat) => {
+        let bytes = <[u8; 8]>::try_from(dat.as_slice()).ok()?;
+        usize::try_from(u64::from_be_bytes(bytes)).ok()
+      }
+      _ => None,
+    };
+    let path = path.iter().map(nat).collect::<Option<_>>()?;
+    let widths = widths.iter().map(nat).collect::<Option<_>>()?;
+    let siblings = siblings
+      .iter()
+      .map(|ds| match ds {
+        ZExpr::Cons(ds) => ds.iter().map(MerkleDigest::from_zexpr).collect(),
+        _ => None,
+      })
+      .collect::<Option<_>>()?;
+    Some(MerkleProof {
+      path,
+      widths,
+      siblings,
+    })
+  }
+}
+
+/// A block tying a Merkle digest to a link: `(<digest:bytes256> <tree:link>)`,
+/// where `tree` is the link of the tree the digest is of. The link of this
+/// block commits to both, so it's what to hand a client which is to check
+/// proofs without fetching the tree.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct MerkleRoot {
+  pub digest: MerkleDigest,
+  pub tree: ZLink,
+}
+
+impl MerkleRoot {
+  /// The root block for `x`, which fails only if `x` can't be linked.
+  pub fn new(x: &ZExpr) -> Result<Self, Invalid> {
+    Ok(MerkleRoot {
+      digest: digest(x),
+      tree: ZLink::of(x)?,
+    })
+  }
+
+  pub fn to_zexpr(&self) -> ZExpr {
+    ZExpr::Cons(vec![self.digest.to_zexpr(), self.tree.to_zexpr()])
+  }
+
+  /// Read back a block written by `to_zexpr`.
+  pub fn from_zexpr(x: &ZExpr) -> Option<Self> {
+    match x {
+      ZExpr::Cons(xs) => match xs.as_slice() {
+        [digest, tree] => Some(MerkleRoot {
+          digest: MerkleDigest::from_zexpr(digest)?,
+          tree: ZLink::from_zexpr(tree)?,
+        }),
+        _ => None,
+      },
+      _ => None,
+    }
+  }
+
+  /// The link of the block, which is what clients are given.
+  pub fn link(&self) -> ZLink {
+    ZLink::of(&self.to_zexpr()).expect("a root block is always valid")
+  }
+}
+
+/// A `ZExpr` with the Merkle digest of every subtree cached, so that after an
+/// edit only the digests on the path to the edit are recomputed. This keeps
+/// `digest` up to date cheaply, which `ZExpr::link` can't be: a link hashes
+/// the whole serialization at once.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct HashedZExpr {
+  digest: MerkleDigest,
+  node: HashedNode,
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+enum HashedNode {
+  Atom(ZType, Vec<u8>),
+  Cons(Vec<HashedZExpr>),
+}
+
+impl HashedZExpr {
+  pub fn new(x: &ZExpr) -> Self {
+    // the conses being built, with the elements still to visit
+    let mut stack: Vec<(Vec<HashedZExpr>, std::slice::Iter<ZExpr>)> = vec![];
+    let mut next = x;
+    loop {
+      match next {
+        ZExpr::Atom(typ, dat) => {
+          let y = HashedZExpr {
+            digest: atom_digest(*typ, dat),
+            node: HashedNode::Atom(*typ, dat.clone()),
+          };
+          match stack.last_mut() {
+            None => return y,
+            Some((ys, _)) => ys.push(y),
+          }
+        }
+        ZExpr::Cons(xs) => {
+          stack.push((Vec::with_capacity(xs.len()), xs.iter()))
+        }
+      }
+      next = loop {
+        let (_, rest) = stack.last_mut().unwrap();
+        if let Some(x) = rest.next() {
+          break x;
+        }
+        let (ys, _) = stack.pop().unwrap();
+        let y = HashedZExpr::cons(ys);
+        match stack.last_mut() {
+          None => return y,
+          Some((zs, _)) => zs.push(y),
+        }
+      };
+    }
+  }
+
+  fn cons(xs: Vec<HashedZExpr>) -> Self {
+    let mut x = HashedZExpr {
+      digest: MerkleDigest([0; 32]),
+      node: HashedNode::Cons(xs),
+    };
+    x.rehash();
+    x
+  }
+
+  /// The Merkle digest of the tree, as `digest` would compute it.
+  pub fn digest(&self) -> MerkleDigest {
+    self.digest
+  }
+
+  /// The elements, if this is a cons.
+  pub fn elements(&self) -> Option<&[HashedZExpr]> {
+    match &self.node {
+      HashedNode::Cons(xs) => Some(xs),
+      HashedNode::Atom(..) => None,
+    }
+  }
+
+  /// The subtree at `path`.
+  pub fn get(&self, path: &[usize]) -> Option<&HashedZExpr> {
+    let mut cur = self;
+    for &i in path {
+      cur = cur.elements()?.get(i)?;
+    }
+    Some(cur)
+  }
+
+  /// Recompute the digest of this cons from its elements' digests.
+  fn rehash(&mut self) {
+    if let HashedNode::Cons(xs) = &self.node {
+      let ds: Vec<MerkleDigest> = xs.iter().map(|x| x.digest).collect();
+      self.digest = cons_digest(&ds);
+    }
+  }
+
+  /// Apply `f` to the subtree at `path`. If it edits the subtree, returning
+  /// `Some`, the conses above it are rehashed, each once, from the bottom up.
+  /// `f` must leave the subtree's own digest up to date.
+  fn edit<T>(
+    &mut self,
+    path: &[usize],
+    f: impl FnOnce(&mut HashedZExpr) -> Option<T>,
+  ) -> Option<T> {
+    self.get(path)?;
+    // the path is taken out of the tree, so each cons on it can be rehashed
+    // once its element on the path is put back
+    let empty = || HashedZExpr {
+      digest: MerkleDigest([0; 32]),
+      node: HashedNode::Cons(vec![]),
+    };
+    let mut spine = Vec::with_capacity(path.len());
+    let mut cur = std::mem::replace(self, empty());
+    for &i in path {
+      let next = match &mut cur.node {
+        HashedNode::Cons(xs) => std::mem::replace(&mut xs[i], empty()),
+        HashedNode::Atom(..) => unreachable!(),
+      };
+      spine.push((cur, i));
+      cur = next;
+    }
+    let result = f(&mut cur);
+    while let Some((mut parent, i)) = spine.pop() {
+      if let HashedNode::Cons(xs) = &mut parent.node {
+        xs[i] = cur;
+      }
+      if result.is_some() {
+        parent.rehash();
+      }
+      cur = parent;
+    }
+    *self = cur;
+    result
+  }
+
+  /// Replace the subtree at `path` with `x`, returning whether there was one
+  /// to replace. Only `x` and the conses above it are hashed.
+  pub fn set(&mut self, path: &[usize], x: &ZExpr) -> bool {
+    self
+      .edit(path, |node| {
+        *node = HashedZExpr::new(x);
+        Some(())
+      })
+      .is_some()
+  }
+
+  /// Insert `x` into the cons at `path`, before the element at `index`.
+  /// Returns false, changing nothing, if there's no cons at `path` or `index`
+  /// is past its end.
+  pub fn insert(&mut self, path: &[usize], index: usize, x: &ZExpr) -> bool {
+    self
+      .edit(path, |node| {
+        match &mut node.node {
+          HashedNode::Cons(xs) if index <= xs.len() => {
+            xs.insert(index, HashedZExpr::new(x))
+          }
+          _ => return None,
+        }
+        node.rehash();
+        Some(())
+      })
+      .is_some()
+  }
+
+  /// Remove and return the subtree at `path`, which must not be empty.
+  pub fn remove(&mut self, path: &[usize]) -> Option<ZExpr> {
+    let (&index, parent) = path.split_last()?;
+    self.edit(parent, |node| {
+      let removed = match &mut node.node {
+        HashedNode::Cons(xs) if index < xs.len() => xs.remove(index),
+        _ => return None,
+      };
+      node.rehash();
+      Some(removed.to_zexpr())
+    })
+  }
+
+  /// A proof of the subtree at `path`, from the cached digests.
+  pub fn proof(&self, path: &[usize]) -> Option<MerkleProof> {
+    let mut widths = Vec::with_capacity(path.len());
+    let mut siblings = Vec::with_capacity(path.len());
+    let mut cur = self;
+    for &i in path {
+      let xs = cur.elements()?;
+      cur = xs.get(i)?;
+      let ds: Vec<MerkleDigest> = xs.iter().map(|y| y.digest).collect();
+      widths.push(xs.len());
+      siblings.push(tree_path(&ds, i));
+    }
+    Some(MerkleProof {
+      path: path.to_vec(),
+      widths,
+      siblings,
+    })
+  }
+
+  pub fn to_zexpr(&self) -> ZExpr {
+    let mut stack: Vec<(Vec<ZExpr>, std::slice::Iter<HashedZExpr>)> = vec![];
+    let mut next = self;
+    loop {
+      match &next.node {
+        HashedNode::Atom(typ, dat) => {
+          let y = ZExpr::Atom(*typ, dat.clone());
+          match stack.last_mut() {
+            None => return y,
+            Some((ys, _)) => ys.push(y),
+          }
+        }
+        HashedNode::Cons(xs) => {
+          stack.push((Vec::with_capacity(xs.len()), xs.iter()))
+        }
+      }
+      next = loop {
+        let (_, rest) = stack.last_mut().unwrap();
+        if let Some(x) = rest.next() {
+          break x;
+        }
+        let (ys, _) = stack.pop().unwrap();
+        match stack.last_mut() {
+          None => return ZExpr::Cons(ys),
+          Some((zs, _)) => zs.push(ZExpr::Cons(ys)),
+        }
+      };
+    }
+  }
+}
+
+impl From<&ZExpr> for HashedZExpr {
+  fn from(x: &ZExpr) -> Self {
+    HashedZExpr::new(x)
+  }
+}
+
+/// Taken apart on a heap stack, as for `ZExpr`.
+impl Drop for HashedZExpr {
+  fn drop(&mut self) {
+    if let HashedNode::Cons(xs) = &mut self.node {
+      let mut stack = std::mem::take(xs);
+      while let Some(mut x) = stack.pop() {
+        if let HashedNode::Cons(ys) = &mut x.node {
+          stack.append(ys);
+        }
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+
+  fn text(s: &str) -> ZExpr {
+    ZExpr::Atom(Text(None), s.as_bytes().to_vec())
+  }
+
+  fn doc() -> ZExpr {
+    ZExpr::Cons(vec![
+      text("title"),
+      ZExpr::Cons(vec![text("a"), text("b"), ZExpr::Cons(vec![])]),
+      ZExpr::Cons(vec![text("c")]),
+    ])
+  }
+
+  #[test]
+  fn zmerkle_digest() {
+    let a = text("a");
+    assert_eq!(digest(&a), atom_digest(Text(None), b"a"));
+    let x = ZExpr::Cons(vec![a.clone(), ZExpr::Cons(vec![])]);
+    let empty = cons_digest(&[]);
+    assert_eq!(digest(&x), cons_digest(&[digest(&a), empty]));
+    assert_ne!(digest(&x).as_bytes(), ZLink::of(&x).unwrap().as_bytes());
+    // the declared length is part of an atom's digest, whatever its data
+    let short = ZExpr::Atom(Nat(Some(2)), vec![1, 2, 3]);
+    let long = ZExpr::Atom(Nat(Some(3)), vec![1, 2, 3]);
+    assert_ne!(digest(&short), digest(&long));
+    // a digest isn't a link, so nothing would try to fetch it
+    let d = digest(&x).to_zexpr();
+    assert_eq!(ZLink::from_zexpr(&d), None);
+    assert_eq!(MerkleDigest::from_zexpr(&d), Some(digest(&x)));
+  }
+
+  /// The tree root as RFC 6962 defines it.
+  fn rfc_root(xs: &[MerkleDigest]) -> Option<MerkleDigest> {
+    if xs.len() <= 1 {
+      return xs.first().copied();
+    }
+    let mut k = 1;
+    while k * 2 < xs.len() {
+      k *= 2;
+    }
+    Some(tree_node(&rfc_root(&xs[..k])?, &rfc_root(&xs[k..])?))
+  }
+
+  #[test]
+  fn zmerkle_tree() {
+    for n in 0..40 {
+      let xs: Vec<MerkleDigest> =
+        (0..n).map(|i| atom_digest(Nat(None), &[i])).collect();
+      assert_eq!(tree_root(&xs), rfc_root(&xs));
+      for i in 0..xs.len() {
+        let path = tree_path(&xs, i);
+        assert!(path.len() <= 6);
+        assert_eq!(tree_climb(i, xs.len(), xs[i], &path), tree_root(&xs));
+      }
+    }
+  }
+
+  #[test]
+  fn zmerkle_proof() {
+    let x = doc();
+    let root = digest(&x);
+    let proof = MerkleProof::new(&x, &[1, 1]).unwrap();
+    assert_eq!(
+      proof.siblings.iter().map(Vec::len).collect::<Vec<_>>(),
+      [2, 2]
+    );
+    assert!(proof.verify(&root, &text("b")));
+    assert!(!proof.verify(&root, &text("a")));
+    assert!(!proof.verify(&digest(&text("b")), &text("b")));
+    // a whole subtree, known only by its digest
+    let proof = MerkleProof::new(&x, &[2]).unwrap();
+    assert!(proof.verify_digest(&root, digest(&ZExpr::Cons(vec![text("c")]))));
+    // the empty path proves the root itself
+    assert!(MerkleProof::new(&x, &[]).unwrap().verify(&root, &x));
+    assert_eq!(MerkleProof::new(&x, &[3]), None);
+    assert_eq!(MerkleProof::new(&x, &[0, 0]), None);
+    // a proof moved to another position fails
+    let mut moved = MerkleProof::new(&x, &[1, 1]).unwrap();
+    moved.path[1] = 0;
+    assert!(!moved.verify(&root, &text("b")));
+    moved.path[1] = 7;
+    assert_eq!(moved.root(digest(&text("b"))), None);
+    // proofs into a wide cons stay small
+    let wide = ZExpr::Cons((0..1000).map(|i| text(&i.to_string())).collect());
+    let proof = MerkleProof::new(&wide, &[617]).unwrap();
+    assert_eq!(proof.siblings[0].len(), 10);
+    assert!(proof.verify(&digest(&wide), &text("617")));
+  }
+
+  #[test]
+  fn zmerkle_proof_zexpr() {
+    let x = doc();
+    let proof = MerkleProof::new(&x, &[1, 2]).unwrap();
+    let y = proof.to_zexpr();
+    assert_eq!(MerkleProof::from_zexpr(&y), Some(proof));
+    let (_, z) = ZExpr::deserialize(&y.serialize()).unwrap();
+    assert_eq!(MerkleProof::from_zexpr(&z).unwrap().path, vec![1, 2]);
+    assert_eq!(MerkleProof::from_zexpr(&text("proof")), None);
+  }
+
+  #[test]
+  fn zmerkle_hashed_edits() {
+    let x = doc();
+    let mut h = HashedZExpr::new(&x);
+    assert_eq!(h.digest(), digest(&x));
+    assert!(h.set(&[1, 1], &text("B")));
+    assert!(h.insert(&[1, 2], 0, &text("new")));
+    assert_eq!(h.remove(&[0]), Some(text("title")));
+    let y = ZExpr::Cons(vec![
+      ZExpr::Cons(vec![text("a"), text("B"), ZExpr::Cons(vec![text("new")])]),
+      ZExpr::Cons(vec![text("c")]),
+    ]);
+    assert_eq!(h.to_zexpr(), y);
+    assert_eq!(h.digest(), digest(&y));
+    assert_eq!(h.get(&[0, 2, 0]).unwrap().digest(), digest(&text("new")));
+    assert!(h.get(&[0, 2, 0]).unwrap().elements().is_none());
+    // edits which don't fit leave the tree alone
+    assert!(!h.set(&[5], &text("x")));
+    assert!(!h.insert(&[0, 0], 0, &text("x")));
+    assert!(!h.insert(&[1], 2, &text("x")));
+    assert_eq!(h.remove(&[]), None);
+    assert_eq!(h.digest(), digest(&y));
+    // the whole tree can be replaced
+    assert!(h.set(&[], &text("x")));
+    assert_eq!(h.digest(), digest(&text("x")));
+  }
+
+  #[test]
+  fn zmerkle_hashed_proof() {
+    let h = HashedZExpr::new(&doc());
+    for path in [&[][..], &[1], &[1, 2], &[2, 0]].iter() {
+      assert_eq!(h.proof(path), MerkleProof::new(&doc(), path));
+    }
+    assert_eq!(h.proof(&[0, 0]), None);
+  }
+
+  #[test]
+  fn zmerkle_proof_link() {
+    let x = doc();
+    let root = MerkleRoot::new(&x).unwrap();
+    assert_eq!(MerkleRoot::from_zexpr(&root.to_zexpr()), Some(root));
+    assert_eq!(root.tree, ZLink::of(&x).unwrap());
+    // the client has only the link, and is sent the root block and a proof
+    let link = root.link();
+    let block = root.to_zexpr().serialize();
+    let proof = MerkleProof::new(&x, &[1, 1]).unwrap();
+    assert!(proof.verify_link(&link, &block, &text("b")));
+    assert!(!proof.verify_link(&link, &block, &text("a")));
+    // a root block with another digest doesn't match the link
+    let forged = MerkleRoot {
+      digest: digest(&text("b")),
+      tree: root.tree,
+    };
+    let forged_block = forged.to_zexpr().serialize();
+    assert!(!proof.verify_link(&link, &forged_block, &text("b")));
+    assert!(!proof.verify_link(&forged.link(), &block, &text("b")));
+    // a block which isn't a root block proves nothing
+    let other = x.serialize();
+    assert!(!proof.verify_link(&ZLink::of(&x).unwrap(), &other, &text("b")));
+  }
+
+  #[test]
+  fn zmerkle_hashed_deep() {
+    let depth = 5000;
+    let mut x = text("leaf");
+    for _ in 0..depth {
+      x = ZExpr::Cons(vec![x]);
+    }
+    let mut h = HashedZExpr::new(&x);
+    let path = vec![0; depth];
+    for i in 0..100 {
+      assert!(h.set(&path, &text(&i.to_string())));
+    }
+    assert_eq!(h.digest(), digest(&h.to_zexpr()));
+    // each digest is computed once, so a proof down the whole depth is cheap
+    let x = h.to_zexpr();
+    let proof = MerkleProof::new(&x, &path).unwrap();
+    assert!(proof.verify(&digest(&x), &text("99")));
+  }
+
+  #[quickcheck]
+  fn zmerkle_hashed_set(x: ZExpr, choices: Vec<usize>, y: ZExpr) -> bool {
+    let mut h = HashedZExpr::new(&x);
+    let mut path = vec![];
+    let mut cur = &x;
+    for c in choices {
+      match cur {
+        ZExpr::Cons(xs) if !xs.is_empty() => {
+          path.push(c % xs.len());
+          cur = &xs[c % xs.len()];
+        }
+        _ => break,
+      }
+    }
+    h.set(&path, &y) && h.digest() == digest(&h.to_zexpr())
+  }
+
+  #[quickcheck]
+  fn zmerkle_proof_any(x: ZExpr, choices: Vec<usize>) -> bool {
+    // follow the choices down the tree, checking a proof at every step
+    let root = digest(&x);
+    let mut path = vec![];
+    let mut y = &x;
+    for c in choices {
+      if !MerkleProof::new(&x, &path).unwrap().verify(&root, y) {
+        return false;
+      }
+      match y {
+        ZExpr::Cons(ys) if !ys.is_empty() => {
+          path.push(c % ys.len());
+          y = &ys[c % ys.len()];
+        }
+        _ => break,
+      }
+    }
+    true
+  }
+}
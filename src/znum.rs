@@ -0,0 +1,646 @@
+//! Arbitrary-precision numbers held in `nat` and `int` atoms.
+//!
+//! `ZNat` reads the data of a `Nat` atom of any width as a big-endian
+//! unsigned integer, and `ZInt` reads an `Int` atom as big-endian two's
+//! complement, as `zconv` does for the fixed-width Rust types. Both convert
+//! back to atoms of type `nat` and `int` with the fewest bytes which hold the
+//! value: zero is the empty atom, and an `Int` keeps a leading sign byte only
+//! when the next byte's high bit disagrees with the sign.
+//!
+//! Arithmetic is the schoolbook kind on 32-bit limbs, which is plenty for
+//! numbers of a few hundred bits. Division truncates toward zero, as for the
+//! Rust integer types.
+
+use core::cmp::Ordering;
+use core::fmt;
+use core::ops::{Add, Mul, Neg, Sub};
+use core::str::FromStr;
+use std::convert::TryFrom;
+
+use crate::zconv::{describe, ZConvError};
+use crate::ztype::ZType;
+use crate::ZExpr;
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ZNumError {
+  /// A decimal string with no digits, or a character which isn't one
+  InvalidDigit,
+  /// A division by zero
+  DivideByZero,
+}
+
+impl fmt::Display for ZNumError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::InvalidDigit => write!(f, "invalid decimal number"),
+      Self::DivideByZero => write!(f, "division by zero"),
+    }
+  }
+}
+
+impl std::error::Error for ZNumError {}
+
+/// An unsigned integer of any size.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct ZNat {
+  /// Little-endian base 2^32 digits, without high zero limbs
+  limbs: Vec<u32>,
+}
+
+impl ZNat {
+  pub fn zero() -> Self {
+    ZNat::default()
+  }
+
+  pub fn is_zero(&self) -> bool {
+    self.limbs.is_empty()
+  }
+
+  fn from_limbs(mut limbs: Vec<u32>) -> Self {
+    while limbs.last() == Some(&0) {
+      limbs.pop();
+    }
+    ZNat { limbs }
+  }
+
+  /// Read big-endian bytes, ignoring leading zeros.
+  pub fn from_be_bytes(bytes: &[u8]) -> Self {
+    let limbs = bytes
+      .rchunks(4)
+      .map(|chunk| chunk.iter().fold(0u32, |n, b| (n << 8) | u32::from(*b)))
+      .collect();
+    ZNat::from_limbs(limbs)
+  }
+
+  /// The shortest big-endian bytes holding the number, empty for zero.
+  pub fn to_be_bytes(&self) -> Vec<u8> {
+    let mut bytes: Vec<u8> = self
+      .limbs
+      .iter()
+      .rev()
+      .flat_map(|l| l.to_be_bytes())
+      .collect();
+    let zeros = bytes.iter().take_while(|b| **b == 0).count();
+    bytes.drain(..zeros);
+    bytes
+  }
+
+  /// The number of bits needed to write the number.
+  pub fn bits(&self) -> u64 {
+    match self.limbs.last() {
+      None => 0,
+      Some(top) => {
+        32 * self.limbs.len() as u64 - u64::from(top.leading_zeros())
+      }
+    }
+  }
+
+  fn bit(&self, i: u64) -> bool {
+    let limb = self.limbs.get((i / 32) as usize).copied().unwrap_or(0);
+    limb >> (i % 32) & 1 == 1
+  }
+
+  pub fn to_u128(&self) -> Option<u128> {
+    if self.limbs.len() > 4 {
+      return None;
+    }
+    Some(
+      self
+        .limbs
+        .iter()
+        .rev()
+        .fold(0, |n, l| (n << 32) | u128::from(*l)),
+    )
+  }
+
+  pub fn checked_sub(&self, other: &ZNat) -> Option<ZNat> {
+    if *self < *other {
+      return None;
+    }
+    let mut limbs = Vec::with_capacity(self.limbs.len());
+    let mut borrow = 0i64;
+    for (i, a) in self.limbs.iter().enumerate() {
+      let b = other.limbs.get(i).copied().unwrap_or(0);
+      let mut d = i64::from(*a) - i64::from(b) - borrow;
+      borrow = 0;
+      if d < 0 {
+        d += 1 << 32;
+        borrow = 1;
+      }
+      limbs.push(d as u32);
+    }
+    Some(ZNat::from_limbs(limbs))
+  }
+
+  /// Divide by a single limb, returning the quotient and remainder.
+  fn div_rem_limb(&self, d: u32) -> (ZNat, u32) {
+    let mut limbs = vec![0; self.limbs.len()];
+    let mut rem = 0u64;
+    for (i, l) in self.limbs.iter().enumerate().rev() {
+      let n = (rem << 32) | u64::from(*l);
+      limbs[i] = (n / u64::from(d)) as u32;
+      rem = n % u64::from(d);
+    }
+    (ZNat::from_limbs(limbs), rem as u32)
+  }
+
+  /// The quotient and remainder of dividing by `d`.
+  pub fn div_rem(&self, d: &ZNat) -> Result<(ZNat, ZNat), ZNumError> {
+    match d.limbs.as_slice() {
+      [] => return Err(ZNumError::DivideByZero),
+      [d] => {
+        let (q, r) = self.div_rem_limb(*d);
+        return Ok((q, ZNat::from(u64::from(r))));
+      }
+      _ => {}
+    }
+    if self < d {
+      return Ok((ZNat::zero(), self.clone()));
+    }
+    // shift and subtract, one bit of the quotient at a time
+    let mut q = vec![0u32; self.limbs.len()];
+    let mut r = ZNat::zero();
+    for i in (0..self.bits()).rev() {
+      r = &r + &r;
+      if self.bit(i) {
+        r = &r + &ZNat::from(1u64);
+      }
+      if let Some(rest) = r.checked_sub(d) {
+        r = rest;
+        q[(i / 32) as usize] |= 1 << (i % 32);
+      }
+    }
+    Ok((ZNat::from_limbs(q), r))
+  }
+
+  pub fn to_zexpr(&self) -> ZExpr {
+    ZExpr::Atom(ZType::Nat(None), self.to_be_bytes())
+  }
+}
+
+impl From<u64> for ZNat {
+  fn from(n: u64) -> Self {
+    ZNat::from_limbs(vec![n as u32, (n >> 32) as u32])
+  }
+}
+
+impl From<u128> for ZNat {
+  fn from(n: u128) -> Self {
+    ZNat::from_be_bytes(&n.to_be_bytes())
+  }
+}
+
+impl Ord for ZNat {
+  fn cmp(&self, other: &Self) -> Ordering {
+    self
+      .limbs
+      .len()
+      .cmp(&other.limbs.len())
+      .then_with(|| self.limbs.iter().rev().cmp(other.limbs.iter().rev()))
+  }
+}
+
+impl PartialOrd for ZNat {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl<'a> Add<&'a ZNat> for &'a ZNat {
+  type Output = ZNat;
+
+  fn add(self, other: &ZNat) -> ZNat {
+    let len = self.limbs.len().max(other.limbs.len());
+    let mut limbs = Vec::with_capacity(len + 1);
+    let mut carry = 0u64;
+    for i in 0..len {
+      let a = self.limbs.get(i).copied().unwrap_or(0);
+      let b = other.limbs.get(i).copied().unwrap_or(0);
+      let n = u64::from(a) + u64::from(b) + carry;
+      limbs.push(n as u32);
+      carry = n >> 32;
+    }
+    limbs.push(carry as u32);
+    ZNat::from_limbs(limbs)
+  }
+}
+
+/// Panics below zero, as the unsigned Rust types do. See `checked_sub`.
+impl<'a> Sub<&'a ZNat> for &'a ZNat {
+  type Output = ZNat;
+
+  fn sub(self, other: &ZNat) -> ZNat {
+    self.checked_sub(other).expect("ZNat subtraction underflow")
+  }
+}
+
+impl<'a> Mul<&'a ZNat> for &'a ZNat {
+  type Output = ZNat;
+
+  fn mul(self, other: &ZNat) -> ZNat {
+    let mut limbs = vec![0u32; self.limbs.len() + other.limbs.len()];
+    for (i, a) in self.limbs.iter().enumerate() {
+      let mut carry = 0u64;
+      for (j, b) in other.limbs.iter().enumerate() {
+        let n = u64::from(*a) * u64::from(*b) + u64::from(limbs[i + j]) + carry;
+        limbs[i + j] = n as u32;
+        carry = n >> 32;
+      }
+      limbs[i + other.limbs.len()] = carry as u32;
+    }
+    ZNat::from_limbs(limbs)
+  }
+}
+
+/// The largest power of ten in a limb, for decimal conversion.
+const TEN9: u32 = 1_000_000_000;
+
+impl fmt::Display for ZNat {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    // base 10^9 digits, least significant first
+    let mut digits = vec![];
+    let mut n = self.clone();
+    while !n.is_zero() {
+      let (q, r) = n.div_rem_limb(TEN9);
+      digits.push(r);
+      n = q;
+    }
+    match digits.split_last() {
+      None => write!(f, "0"),
+      Some((top, rest)) => {
+        write!(f, "{}", top)?;
+        rest.iter().rev().try_for_each(|d| write!(f, "{:09}", d))
+      }
+    }
+  }
+}
+
+impl FromStr for ZNat {
+  type Err = ZNumError;
+
+  fn from_str(s: &str) -> Result<Self, ZNumError> {
+    if s.is_empty() || !s.bytes().all(|b| b.is_ascii_digit()) {
+      return Err(ZNumError::InvalidDigit);
+    }
+    let mut n = ZNat::zero();
+    // nine digits at a time, the first group taking what's left over
+    let mut start = 0;
+    let mut end = match s.len() % 9 {
+      0 => 9,
+      r => r,
+    };
+    while start < s.len() {
+      let group: u32 = s[start..end].parse().unwrap();
+      let scale = 10u64.pow((end - start) as u32);
+      n = &(&n * &ZNat::from(scale)) + &ZNat::from(u64::from(group));
+      start = end;
+      end += 9;
+    }
+    Ok(n)
+  }
+}
+
+impl From<ZNat> for ZExpr {
+  fn from(n: ZNat) -> Self {
+    n.to_zexpr()
+  }
+}
+
+impl TryFrom<&ZExpr> for ZNat {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Nat(_), dat) => Ok(ZNat::from_be_bytes(dat)),
+      _ => Err(ZConvError::Mismatch("nat", describe(x))),
+    }
+  }
+}
+
+/// A signed integer of any size.
+#[derive(PartialEq, Eq, Hash, Clone, Debug, Default)]
+pub struct ZInt {
+  /// Never set for zero
+  neg: bool,
+  mag: ZNat,
+}
+
+impl ZInt {
+  pub fn zero() -> Self {
+    ZInt::default()
+  }
+
+  fn new(neg: bool, mag: ZNat) -> Self {
+    ZInt {
+      neg: neg && !mag.is_zero(),
+      mag,
+    }
+  }
+
+  pub fn is_negative(&self) -> bool {
+    self.neg
+  }
+
+  /// The absolute value.
+  pub fn magnitude(&self) -> &ZNat {
+    &self.mag
+  }
+
+  /// Read big-endian two's complement bytes, ignoring redundant sign bytes.
+  pub fn from_be_bytes(bytes: &[u8]) -> Self {
+    if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+      // negate: the magnitude is the complement plus one
+      let complement: Vec<u8> = bytes.iter().map(|b| !b).collect();
+      let mag = &ZNat::from_be_bytes(&complement) + &ZNat::from(1u64);
+      ZInt::new(true, mag)
+    } else {
+      ZInt::new(false, ZNat::from_be_bytes(bytes))
+    }
+  }
+
+  /// The shortest big-endian two's complement bytes holding the number,
+  /// empty for zero.
+  pub fn to_be_bytes(&self) -> Vec<u8> {
+    if !self.neg {
+      let mut bytes = self.mag.to_be_bytes();
+      if bytes.first().is_some_and(|b| b & 0x80 != 0) {
+        bytes.insert(0, 0);
+      }
+      return bytes;
+    }
+    let mut bytes = (&self.mag - &ZNat::from(1u64)).to_be_bytes();
+    for b in bytes.iter_mut() {
+      *b = !*b;
+    }
+    if bytes.first().map_or(true, |b| b & 0x80 == 0) {
+      bytes.insert(0, 0xff);
+    }
+    bytes
+  }
+
+  pub fn to_i128(&self) -> Option<i128> {
+    let mag = self.mag.to_u128()?;
+    if self.neg {
+      0i128.checked_sub_unsigned(mag)
+    } else {
+      i128::try_from(mag).ok()
+    }
+  }
+
+  /// The quotient rounded toward zero, and the remainder, which takes the
+  /// sign of the dividend.
+  pub fn div_rem(&self, d: &ZInt) -> Result<(ZInt, ZInt), ZNumError> {
+    let (q, r) = self.mag.div_rem(&d.mag)?;
+    Ok((ZInt::new(self.neg != d.neg, q), ZInt::new(self.neg, r)))
+  }
+
+  pub fn to_zexpr(&self) -> ZExpr {
+    ZExpr::Atom(ZType::Int(None), self.to_be_bytes())
+  }
+}
+
+impl From<i64> for ZInt {
+  fn from(n: i64) -> Self {
+    ZInt::new(n < 0, ZNat::from(n.unsigned_abs()))
+  }
+}
+
+impl From<i128> for ZInt {
+  fn from(n: i128) -> Self {
+    ZInt::new(n < 0, ZNat::from(n.unsigned_abs()))
+  }
+}
+
+impl From<ZNat> for ZInt {
+  fn from(n: ZNat) -> Self {
+    ZInt::new(false, n)
+  }
+}
+
+impl Ord for ZInt {
+  fn cmp(&self, other: &Self) -> Ordering {
+    match (self.neg, other.neg) {
+      (false, false) => self.mag.cmp(&other.mag),
+      (true, true) => other.mag.cmp(&self.mag),
+      (false, true) => Ordering::Greater,
+      (true, false) => Ordering::Less,
+    }
+  }
+}
+
+impl PartialOrd for ZInt {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Neg for &ZInt {
+  type Output = ZInt;
+
+  fn neg(self) -> ZInt {
+    ZInt::new(!self.neg, self.mag.clone())
+  }
+}
+
+impl<'a> Add<&'a ZInt> for &'a ZInt {
+  type Output = ZInt;
+
+  fn add(self, other: &ZInt) -> ZInt {
+    if self.neg == other.neg {
+      return ZInt::new(self.neg, &self.mag + &other.mag);
+    }
+    // opposite signs: the larger magnitude wins
+    match self.mag.checked_sub(&other.mag) {
+      Some(mag) => ZInt::new(self.neg, mag),
+      None => ZInt::new(other.neg, &other.mag - &self.mag),
+    }
+  }
+}
+
+impl<'a> Sub<&'a ZInt> for &'a ZInt {
+  type Output = ZInt;
+
+  fn sub(self, other: &ZInt) -> ZInt {
+    self + &-other
+  }
+}
+
+impl<'a> Mul<&'a ZInt> for &'a ZInt {
+  type Output = ZInt;
+
+  fn mul(self, other: &ZInt) -> ZInt {
+    ZInt::new(self.neg != other.neg, &self.mag * &other.mag)
+  }
+}
+
+impl fmt::Display for ZInt {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    if self.neg {
+      write!(f, "-")?;
+    }
+    write!(f, "{}", self.mag)
+  }
+}
+
+impl FromStr for ZInt {
+  type Err = ZNumError;
+
+  fn from_str(s: &str) -> Result<Self, ZNumError> {
+    match s.strip_prefix('-') {
+      Some(digits) => Ok(ZInt::new(true, digits.parse()?)),
+      None => Ok(ZInt::new(false, s.parse()?)),
+    }
+  }
+}
+
+impl From<ZInt> for ZExpr {
+  fn from(n: ZInt) -> Self {
+    n.to_zexpr()
+  }
+}
+
+impl TryFrom<&ZExpr> for ZInt {
+  type Error = ZConvError;
+
+  fn try_from(x: &ZExpr) -> Result<Self, ZConvError> {
+    match x {
+      ZExpr::Atom(ZType::Int(_), dat) => Ok(ZInt::from_be_bytes(dat)),
+      _ => Err(ZConvError::Mismatch("int", describe(x))),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+
+  fn nat(s: &str) -> ZNat {
+    s.parse().unwrap()
+  }
+
+  fn int(s: &str) -> ZInt {
+    s.parse().unwrap()
+  }
+
+  #[test]
+  fn znum_decimal() {
+    let max256 = "115792089237316195423570985008687907853269984665640564039457\
+                  584007913129639935";
+    let n = nat(max256);
+    assert_eq!(n.bits(), 256);
+    assert_eq!(n.to_be_bytes(), vec![0xff; 32]);
+    assert_eq!(n.to_string(), max256);
+    assert_eq!(nat("0").to_string(), "0");
+    assert_eq!(nat("000123").to_string(), "123");
+    assert_eq!(nat("1000000000").to_string(), "1000000000");
+    assert_eq!("".parse::<ZNat>(), Err(ZNumError::InvalidDigit));
+    assert_eq!("12a".parse::<ZNat>(), Err(ZNumError::InvalidDigit));
+    assert_eq!("-1".parse::<ZNat>(), Err(ZNumError::InvalidDigit));
+    assert_eq!(int("-0"), ZInt::zero());
+    assert_eq!(int(&format!("-{}", max256)).to_string().len(), 79);
+    assert_eq!("-".parse::<ZInt>(), Err(ZNumError::InvalidDigit));
+  }
+
+  #[test]
+  fn znum_atoms() {
+    // minimal forms
+    assert_eq!(ZNat::zero().to_zexpr(), ZExpr::Atom(Nat(None), vec![]));
+    assert_eq!(nat("256").to_be_bytes(), vec![1, 0]);
+    let two = |n: i64| ZInt::from(n).to_be_bytes();
+    assert_eq!(two(0), Vec::<u8>::new());
+    assert_eq!(two(-1), vec![0xff]);
+    assert_eq!(two(127), vec![0x7f]);
+    assert_eq!(two(128), vec![0x00, 0x80]);
+    assert_eq!(two(-128), vec![0x80]);
+    assert_eq!(two(-129), vec![0xff, 0x7f]);
+    assert_eq!(two(-256), vec![0xff, 0x00]);
+    // any width is read, and normalized
+    let x = ZExpr::Atom(Nat(Some(4)), vec![0, 0, 1, 0]);
+    assert_eq!(
+      ZNat::try_from(&x).unwrap().to_zexpr(),
+      nat("256").to_zexpr()
+    );
+    let x = ZExpr::Atom(Int(Some(4)), vec![0xff, 0xff, 0xff, 0x7f]);
+    assert_eq!(ZInt::try_from(&x), Ok(ZInt::from(-129i64)));
+    assert_eq!(
+      ZInt::try_from(&ZExpr::from(i128::MIN)),
+      Ok(i128::MIN.into())
+    );
+    assert!(ZNat::try_from(&ZExpr::from(1i8)).is_err());
+    // and agrees with the fixed-width conversions
+    let n = nat("340282366920938463463374607431768211455");
+    assert_eq!(u128::try_from(&n.to_zexpr()), Ok(u128::MAX));
+    let n = &n + &nat("1");
+    assert_eq!(n.to_u128(), None);
+    assert!(u128::try_from(&n.to_zexpr()).is_err());
+  }
+
+  #[test]
+  fn znum_division() {
+    let a = nat("123456789012345678901234567890123456789");
+    let b = nat("9876543210987654321");
+    let (q, r) = a.div_rem(&b).unwrap();
+    assert!(r < b);
+    assert_eq!(&(&q * &b) + &r, a);
+    assert_eq!(a.div_rem(&ZNat::zero()), Err(ZNumError::DivideByZero));
+    assert_eq!(b.div_rem(&a).unwrap(), (ZNat::zero(), b.clone()));
+    assert_eq!(nat("5").checked_sub(&nat("6")), None);
+    let (q, r) = int("-7").div_rem(&int("2")).unwrap();
+    assert_eq!((q, r), (int("-3"), int("-1")));
+    let (q, r) = int("7").div_rem(&int("-2")).unwrap();
+    assert_eq!((q, r), (int("-3"), int("1")));
+  }
+
+  #[quickcheck]
+  fn znum_nat_ops(a: u64, b: u64, c: u64) -> bool {
+    let (za, zb) = (ZNat::from(a), ZNat::from(b));
+    let wide = ZNat::from(u128::from(a) << 64 | u128::from(c));
+    let (a, b, c) = (u128::from(a), u128::from(b), u128::from(c));
+    let wide_u = a << 64 | c;
+    (&za + &zb).to_u128() == Some(a + b)
+      && (&za * &zb).to_u128() == Some(a * b)
+      && za.checked_sub(&zb).map(|n| n.to_u128()) == a.checked_sub(b).map(Some)
+      && za.cmp(&zb) == a.cmp(&b)
+      && match wide.div_rem(&zb) {
+        Ok((q, r)) => {
+          q.to_u128() == Some(wide_u / b) && r.to_u128() == Some(wide_u % b)
+        }
+        Err(_) => b == 0,
+      }
+      && ZNat::from_be_bytes(&wide.to_be_bytes()) == wide
+      && wide.to_string() == wide_u.to_string()
+      && wide.to_string().parse() == Ok(wide)
+  }
+
+  #[quickcheck]
+  fn znum_int_ops(a: i64, b: i64) -> bool {
+    let (za, zb) = (ZInt::from(a), ZInt::from(b));
+    let (a, b) = (i128::from(a), i128::from(b));
+    (&za + &zb).to_i128() == Some(a + b)
+      && (&za - &zb).to_i128() == Some(a - b)
+      && (&za * &zb).to_i128() == Some(a * b)
+      && za.cmp(&zb) == a.cmp(&b)
+      && match za.div_rem(&zb) {
+        Ok((q, r)) => q.to_i128() == Some(a / b) && r.to_i128() == Some(a % b),
+        Err(_) => b == 0,
+      }
+      && ZInt::from_be_bytes(&za.to_be_bytes()) == za
+      && za.to_be_bytes().len() <= 8
+      && za.to_be_bytes() == {
+        // the minimal two's complement form of the i128
+        let bytes = a.to_be_bytes();
+        let sign = if a < 0 { 0xff } else { 0 };
+        let mut start = bytes.iter().position(|b| *b != sign).unwrap_or(16);
+        if start > 0 && start < 16 && (bytes[start] & 0x80 != 0) != (a < 0) {
+          start -= 1;
+        }
+        if a < 0 && start == 16 {
+          start = 15;
+        }
+        bytes[start..].to_vec()
+      }
+      && za.to_string() == a.to_string()
+      && za.to_string().parse() == Ok(za)
+  }
+}
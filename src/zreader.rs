@@ -0,0 +1,327 @@
+use core::fmt;
+use std::io;
+use std::io::Read;
+
+use crate::limits::{DecodeLimit, DecodeLimits};
+use crate::ztype::ZType;
+use crate::ZExpr;
+use crate::ZHeader;
+
+/// Largest number of cons elements reserved ahead of reading them.
+const MAX_PREALLOC: u64 = 1024;
+
+#[derive(Debug)]
+pub enum ZReadError {
+  Io(io::Error),
+  /// The stream ended partway through an expression: (offset, part being read)
+  Truncated(u64, &'static str),
+  /// An atom had an unknown type code: (offset of the code, code)
+  InvalidZTypeCode(u64, Vec<u8>),
+  /// A header broke the reader's limits: (offset of the header, limit)
+  LimitExceeded(u64, DecodeLimit),
+}
+
+impl fmt::Display for ZReadError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Io(e) => write!(f, "{}", e),
+      Self::Truncated(pos, part) => {
+        write!(f, "input ended at byte {} while reading {}", pos, part)
+      }
+      Self::InvalidZTypeCode(pos, code) => {
+        write!(f, "invalid type code {:?} at byte {}", code, pos)
+      }
+      Self::LimitExceeded(pos, limit) => write!(f, "{} at byte {}", limit, pos),
+    }
+  }
+}
+
+impl std::error::Error for ZReadError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for ZReadError {
+  fn from(e: io::Error) -> Self {
+    ZReadError::Io(e)
+  }
+}
+
+/// Decodes a sequence of serialized `ZExpr`s from a byte stream, one
+/// expression at a time. Only the expression being decoded is held in memory.
+pub struct ZExprReader<R> {
+  inner: R,
+  pos: u64,
+  limits: DecodeLimits,
+}
+
+impl<R: Read> ZExprReader<R> {
+  pub fn new(inner: R) -> Self {
+    ZExprReader::with_limits(inner, DecodeLimits::unlimited())
+  }
+
+  /// A reader which checks every expression against `limits`, failing with
+  /// `LimitExceeded` before reading or allocating for an oversized part.
+  pub fn with_limits(inner: R, limits: DecodeLimits) -> Self {
+    ZExprReader {
+      inner,
+      pos: 0,
+      limits,
+    }
+  }
+
+  /// The number of bytes consumed from the stream so far.
+  pub fn position(&self) -> u64 {
+    self.pos
+  }
+
+  pub fn get_ref(&self) -> &R {
+    &self.inner
+  }
+
+  pub fn into_inner(self) -> R {
+    self.inner
+  }
+
+  /// Read the next expression, or `None` if the stream ends cleanly before
+  /// it begins.
+  pub fn read_expr(&mut self) -> Result<Option<ZExpr>, ZReadError> {
+    let start = self.pos;
+    let mut size = [0u8; 1];
+    if self.fill(&mut size)? == 0 {
+      return Ok(None);
+    }
+    // the conses still being filled, with the number of elements they lack
+    let mut stack: Vec<(Vec<ZExpr>, u64)> = vec![];
+    let mut first = true;
+    loop {
+      if !first {
+        self.read_exact(&mut size, "size byte")?;
+      }
+      first = false;
+      let header_pos = self.pos - 1;
+      let header = self.read_header(size[0])?;
+      self
+        .check_limits(&header, start, stack.len())
+        .map_err(|e| ZReadError::LimitExceeded(header_pos, e))?;
+      let mut x = match header {
+        ZHeader::Atom(typ, dat_len) => {
+          let mut dat = vec![];
+          let n = (&mut self.inner).take(dat_len).read_to_end(&mut dat)?;
+          self.pos += n as u64;
+          if (n as u64) < dat_len {
+            return Err(ZReadError::Truncated(self.pos, "atom data"));
+          }
+          ZExpr::Atom(typ, dat)
+        }
+        ZHeader::Cons(0) => ZExpr::Cons(vec![]),
+        ZHeader::Cons(len) => {
+          let xs = Vec::with_capacity(len.min(MAX_PREALLOC) as usize);
+          stack.push((xs, len));
+          continue;
+        }
+      };
+      // hand the finished expression up to its parents
+      loop {
+        match stack.last_mut() {
+          None => return Ok(Some(x)),
+          Some((xs, remaining)) => {
+            xs.push(x);
+            *remaining -= 1;
+            if *remaining > 0 {
+              break;
+            }
+          }
+        }
+        let (xs, _) = stack.pop().unwrap();
+        x = ZExpr::Cons(xs);
+      }
+    }
+  }
+
+  /// Check a header read at `depth` conses down, in an expression starting at
+  /// byte `start`.
+  fn check_limits(
+    &self,
+    header: &ZHeader,
+    start: u64,
+    depth: usize,
+  ) -> Result<(), DecodeLimit> {
+    let consumed = self.pos - start;
+    match *header {
+      ZHeader::Atom(_, dat_len) => {
+        self.limits.check_atom_len(dat_len)?;
+        self.limits.check_total(consumed.saturating_add(dat_len))
+      }
+      ZHeader::Cons(len) => {
+        self.limits.check_cons_len(len)?;
+        self.limits.check_depth(depth + 1)?;
+        self.limits.check_total(consumed)
+      }
+    }
+  }
+
+  fn read_header(&mut self, size: u8) -> Result<ZHeader, ZReadError> {
+    let (is_atom, is_sized, typ_len, dat_len_len) = ZHeader::size_fields(size);
+    if is_atom {
+      let code_pos = self.pos;
+      let mut code = [0u8; 8];
+      let code = &mut code[..typ_len as usize];
+      self.read_exact(code, "type code")?;
+      let dat_len = self.read_len(dat_len_len)?;
+      let len = if is_sized { Some(dat_len) } else { None };
+      match ZType::deserialize(code, len) {
+        Some(typ) => Ok(ZHeader::Atom(typ, dat_len)),
+        None => Err(ZReadError::InvalidZTypeCode(code_pos, code.to_vec())),
+      }
+    } else {
+      Ok(ZHeader::Cons(self.read_len(dat_len_len)?))
+    }
+  }
+
+  fn read_len(&mut self, len_len: u8) -> Result<u64, ZReadError> {
+    let mut buf = [0u8; 8];
+    let buf = &mut buf[..len_len as usize];
+    self.read_exact(buf, "length prefix")?;
+    Ok(buf.iter().fold(0, |acc, &x| (acc * 256) + x as u64))
+  }
+
+  fn read_exact(
+    &mut self,
+    buf: &mut [u8],
+    part: &'static str,
+  ) -> Result<(), ZReadError> {
+    if self.fill(buf)? < buf.len() {
+      Err(ZReadError::Truncated(self.pos, part))
+    } else {
+      Ok(())
+    }
+  }
+
+  /// Read into `buf` until it is full or the stream ends, returning the
+  /// number of bytes read.
+  fn fill(&mut self, buf: &mut [u8]) -> Result<usize, ZReadError> {
+    let mut n = 0;
+    while n < buf.len() {
+      match self.inner.read(&mut buf[n..]) {
+        Ok(0) => break,
+        Ok(k) => n += k,
+        Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+        Err(e) => return Err(ZReadError::Io(e)),
+      }
+    }
+    self.pos += n as u64;
+    Ok(n)
+  }
+}
+
+impl<R: Read> Iterator for ZExprReader<R> {
+  type Item = Result<ZExpr, ZReadError>;
+
+  fn next(&mut self) -> Option<Self::Item> {
+    self.read_expr().transpose()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+
+  /// A reader which hands out one byte per call.
+  struct Trickle<'a>(&'a [u8]);
+
+  impl<'a> Read for Trickle<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+      match (self.0.split_first(), buf.first_mut()) {
+        (Some((b, rest)), Some(out)) => {
+          *out = *b;
+          self.0 = rest;
+          Ok(1)
+        }
+        _ => Ok(0),
+      }
+    }
+  }
+
+  #[test]
+  fn reader_stream() {
+    let a = ZExpr::Atom(Text(None), b"hello".to_vec());
+    let b = ZExpr::Cons(vec![a.clone(), ZExpr::Cons(vec![]), a.clone()]);
+    let c = ZExpr::Atom(Nat(Some(2)), vec![1, 0]);
+    let mut bytes = vec![];
+    for x in &[a.clone(), b.clone(), c.clone()] {
+      bytes.extend(x.serialize());
+    }
+    let xs: Vec<ZExpr> = ZExprReader::new(Trickle(&bytes))
+      .collect::<Result<_, _>>()
+      .unwrap();
+    assert_eq!(xs, vec![a, b, c]);
+  }
+
+  #[test]
+  fn reader_truncated() {
+    let x = ZExpr::Cons(vec![ZExpr::Atom(Bytes(None), vec![1, 2, 3])]);
+    let bytes = x.serialize();
+    let parts = [(1, "length prefix"), (3, "type code"), (5, "atom data")];
+    for (n, part) in parts.iter() {
+      let mut r = ZExprReader::new(&bytes[..*n]);
+      match r.read_expr() {
+        Err(ZReadError::Truncated(pos, p)) => {
+          assert_eq!((pos, p), (*n as u64, *part))
+        }
+        y => panic!("unexpected {:?}", y),
+      }
+    }
+    let mut bad = bytes.clone();
+    bad[3] = 0xff;
+    match ZExprReader::new(&bad[..]).read_expr() {
+      Err(ZReadError::InvalidZTypeCode(3, code)) => {
+        assert_eq!(code, vec![0xff])
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+    assert!(ZExprReader::new(&[][..]).read_expr().unwrap().is_none());
+  }
+
+  #[test]
+  fn reader_limits() {
+    let limits = DecodeLimits {
+      max_depth: 1,
+      ..DecodeLimits::default()
+    };
+    let x = ZExpr::Cons(vec![ZExpr::Atom(Bytes(None), vec![])]);
+    let y = ZExpr::Cons(vec![x.clone()]);
+    let mut bytes = x.serialize();
+    bytes.extend(y.serialize());
+    let mut r = ZExprReader::with_limits(&bytes[..], limits);
+    assert_eq!(r.read_expr().unwrap(), Some(x));
+    match r.read_expr() {
+      Err(ZReadError::LimitExceeded(7, DecodeLimit::Depth(2))) => {}
+      y => panic!("unexpected {:?}", y),
+    }
+    // an atom claiming 2^56 bytes is refused before any of it is read
+    let hostile = [0b0000_0111, 0x00, 1, 0, 0, 0, 0, 0, 0, 0];
+    let mut r = ZExprReader::with_limits(&hostile[..], DecodeLimits::default());
+    match r.read_expr() {
+      Err(ZReadError::LimitExceeded(0, DecodeLimit::AtomLength(n))) => {
+        assert_eq!(n, 1 << 56)
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
+  #[quickcheck]
+  fn reader_matches_deserialize(x: ZExpr) -> bool {
+    let bytes = x.serialize();
+    let mut r = ZExprReader::new(Trickle(&bytes));
+    match r.read_expr() {
+      Ok(Some(y)) => x == y && r.position() == bytes.len() as u64,
+      _ => false,
+    }
+  }
+}
@@ -0,0 +1,211 @@
+use nom::bytes::complete::take;
+use nom::error::ErrorKind;
+use nom::Err;
+use nom::IResult;
+
+use crate::ztype::ZType;
+use crate::ZExpr;
+use crate::ZExprDeserialError;
+use crate::ZHeader;
+
+/// A `ZExpr` borrowed from its serialized bytes. Atom data points into the
+/// input buffer, and the elements of a cons are decoded only when they are
+/// visited, so walking a `ZExprRef` never allocates.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ZExprRef<'a> {
+  Atom(ZType, &'a [u8]),
+  Cons(ZConsRef<'a>),
+}
+
+/// The still-serialized elements of a cons.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct ZConsRef<'a> {
+  len: u64,
+  dat: &'a [u8],
+}
+
+impl<'a> ZExprRef<'a> {
+  /// Borrow the first expression in `i`. The elements of a cons are checked
+  /// to be well-formed, but are not decoded.
+  pub fn deserialize(
+    i: &'a [u8],
+  ) -> IResult<&'a [u8], ZExprRef<'a>, ZExprDeserialError<&'a [u8]>> {
+    let (i, header) = ZHeader::deserialize(i)?;
+    match header {
+      ZHeader::Atom(typ, dat_len) => {
+        let (i, dat) = take(dat_len)(i)?;
+        Ok((i, ZExprRef::Atom(typ, dat)))
+      }
+      ZHeader::Cons(len) => {
+        let (rest, _) = skip(i, len)?;
+        let dat = &i[..i.len() - rest.len()];
+        Ok((rest, ZExprRef::Cons(ZConsRef { len, dat })))
+      }
+    }
+  }
+
+  /// Copy into an owned `ZExpr`.
+  pub fn to_zexpr(&self) -> ZExpr {
+    match self {
+      Self::Atom(typ, dat) => ZExpr::Atom(*typ, dat.to_vec()),
+      Self::Cons(xs) => {
+        // decode the elements in one pass; going through `iter` would skip
+        // over each nested cons once per level
+        let mut i = xs.dat;
+        let mut ys = Vec::with_capacity(xs.len as usize);
+        for _ in 0..xs.len {
+          let (rest, y) = ZExpr::deserialize(i)
+            .expect("cons elements are checked when the cons is read");
+          ys.push(y);
+          i = rest;
+        }
+        ZExpr::Cons(ys)
+      }
+    }
+  }
+}
+
+impl<'a> From<ZExprRef<'a>> for ZExpr {
+  fn from(x: ZExprRef<'a>) -> Self {
+    x.to_zexpr()
+  }
+}
+
+impl<'a> ZConsRef<'a> {
+  pub fn len(&self) -> u64 {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+
+  /// The serialized elements of the cons, without its header.
+  pub fn as_bytes(&self) -> &'a [u8] {
+    self.dat
+  }
+
+  pub fn iter(&self) -> ZConsIter<'a> {
+    ZConsIter {
+      rest: self.dat,
+      remaining: self.len,
+    }
+  }
+
+  /// The `n`th element, skipping over the ones before it.
+  pub fn get(&self, n: u64) -> Option<ZExprRef<'a>> {
+    if n >= self.len {
+      return None;
+    }
+    let (i, _) = skip(self.dat, n).ok()?;
+    let (_, x) = ZExprRef::deserialize(i).ok()?;
+    Some(x)
+  }
+}
+
+impl<'a> IntoIterator for ZConsRef<'a> {
+  type Item = ZExprRef<'a>;
+  type IntoIter = ZConsIter<'a>;
+
+  fn into_iter(self) -> ZConsIter<'a> {
+    self.iter()
+  }
+}
+
+/// Iterates over the elements of a `ZConsRef`.
+#[derive(Clone, Debug)]
+pub struct ZConsIter<'a> {
+  rest: &'a [u8],
+  remaining: u64,
+}
+
+impl<'a> Iterator for ZConsIter<'a> {
+  type Item = ZExprRef<'a>;
+
+  fn next(&mut self) -> Option<ZExprRef<'a>> {
+    if self.remaining == 0 {
+      return None;
+    }
+    let (rest, x) = ZExprRef::deserialize(self.rest).ok()?;
+    self.rest = rest;
+    self.remaining -= 1;
+    Some(x)
+  }
+}
+
+/// Skip over `n` serialized expressions, returning the input after them.
+/// Nested conses are walked with a counter rather than by recursion.
+fn skip(mut i: &[u8], n: u64) -> IResult<&[u8], (), ZExprDeserialError<&[u8]>> {
+  let mut remaining = n;
+  while remaining > 0 {
+    let (rest, header) = ZHeader::deserialize(i)?;
+    remaining -= 1;
+    match header {
+      ZHeader::Atom(_, dat_len) => {
+        let (rest, _) = take(dat_len)(rest)?;
+        i = rest;
+      }
+      ZHeader::Cons(len) => {
+        remaining = remaining.checked_add(len).ok_or(Err::Error(
+          ZExprDeserialError::NomErr(rest, ErrorKind::TooLarge),
+        ))?;
+        i = rest;
+      }
+    }
+  }
+  Ok((i, ()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+
+  #[test]
+  fn zref_borrows() {
+    let a = ZExpr::Atom(Bytes(None), vec![1, 2, 3]);
+    let b = ZExpr::Atom(Nat(Some(1)), vec![7]);
+    let x = ZExpr::Cons(vec![a.clone(), ZExpr::Cons(vec![b.clone()]), b]);
+    let bytes = x.serialize();
+    let (rest, r) = ZExprRef::deserialize(&bytes).unwrap();
+    assert!(rest.is_empty());
+    match r {
+      ZExprRef::Cons(xs) => {
+        assert_eq!(xs.len(), 3);
+        match xs.get(0) {
+          Some(ZExprRef::Atom(Bytes(None), dat)) => {
+            assert_eq!(dat, &[1, 2, 3]);
+            // the atom data is a view into the input buffer
+            let offset = dat.as_ptr() as usize - bytes.as_ptr() as usize;
+            assert_eq!(&bytes[offset..offset + 3], &[1, 2, 3]);
+          }
+          y => panic!("unexpected {:?}", y),
+        }
+        assert_eq!(xs.get(2), Some(ZExprRef::Atom(Nat(Some(1)), &[7])));
+        assert_eq!(xs.get(3), None);
+        assert_eq!(xs.iter().count(), 3);
+      }
+      y => panic!("unexpected {:?}", y),
+    }
+    assert_eq!(r.to_zexpr(), x);
+  }
+
+  #[test]
+  fn zref_truncated() {
+    let x = ZExpr::Cons(vec![ZExpr::Cons(vec![ZExpr::Atom(
+      Bytes(None),
+      vec![1, 2, 3],
+    )])]);
+    let bytes = x.serialize();
+    assert!(ZExprRef::deserialize(&bytes[..bytes.len() - 1]).is_err());
+  }
+
+  #[quickcheck]
+  fn zref_to_zexpr(x: ZExpr) -> bool {
+    let bytes = x.serialize();
+    match ZExprRef::deserialize(&bytes) {
+      Ok((rest, y)) => rest.is_empty() && y.to_zexpr() == x,
+      _ => false,
+    }
+  }
+}
@@ -0,0 +1,697 @@
+use std::convert::TryFrom;
+
+use serde::de::{self, DeserializeOwned, DeserializeSeed, Visitor};
+
+use crate::zbase::ZBaseError;
+use crate::zconv::{be_i128, be_u128, describe};
+use crate::zserde::{PathSegment, ZSerdeError};
+use crate::ztype::{ZType, ZTypeError};
+use crate::{ZExpr, ZExprDeserialError, ZExprError};
+
+/// Deserialize a value from a `ZExpr` tree.
+pub fn from_zexpr<T: DeserializeOwned>(x: &ZExpr) -> Result<T, ZSerdeError> {
+  T::deserialize(Deserializer::new(x))
+}
+
+/// Deserialize a value from the binary format accepted by
+/// `ZExpr::deserialize`.
+pub fn from_slice<T: DeserializeOwned>(i: &[u8]) -> Result<T, ZSerdeError> {
+  match ZExpr::deserialize(i) {
+    Ok(([], x)) => from_zexpr(&x),
+    Ok((rest, _)) => Err(ZSerdeError::TrailingInput(rest.len())),
+    Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+      let kind = deserial_kind(&e);
+      Err(ZSerdeError::Deserial(i.len() - e.rest().len(), kind))
+    }
+    Err(nom::Err::Incomplete(_)) => Err(ZSerdeError::Deserial(
+      i.len(),
+      String::from("unexpected end"),
+    )),
+  }
+}
+
+/// What went wrong, without the input the error holds, which may be large.
+fn deserial_kind(e: &ZExprDeserialError<&[u8]>) -> String {
+  match e {
+    ZExprDeserialError::InvalidZTypeCode(_, code) => {
+      format!("invalid type code {:02x?}", code)
+    }
+    ZExprDeserialError::LimitExceeded(_, limit) => limit.to_string(),
+    ZExprDeserialError::NonCanonical(_, e) => format!("{:?}", e),
+    ZExprDeserialError::Invalid(_, e) => e.to_string(),
+    ZExprDeserialError::NomErr(_, kind) => kind.description().to_owned(),
+  }
+}
+
+/// Deserialize a value from the text format accepted by `zexpr::parse`.
+pub fn from_str<T: DeserializeOwned>(i: &str) -> Result<T, ZSerdeError> {
+  match crate::parse(i) {
+    Ok(("", x)) => from_zexpr(&x),
+    Ok((rest, _)) => Err(ZSerdeError::TrailingInput(rest.len())),
+    Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => {
+      let kind = parse_kind(&e);
+      Err(ZSerdeError::Parse(i.len() - e.rest().len(), kind))
+    }
+    Err(nom::Err::Incomplete(_)) => {
+      Err(ZSerdeError::Parse(i.len(), String::from("unexpected end")))
+    }
+  }
+}
+
+/// Like `deserial_kind`, for the text syntax.
+fn parse_kind(e: &ZExprError<&str>) -> String {
+  match e {
+    ZExprError::ZTypeErr(_, e) => match e {
+      ZTypeError::UnalignedTypeIndex(_, n) => {
+        format!("type index {} isn't a multiple of 8", n)
+      }
+      ZTypeError::InvalidU64TypeIndex(_, e) => format!("type index: {}", e),
+      ZTypeError::InvalidExtCode(_) => String::from("invalid ext type code"),
+      ZTypeError::NomErr(_, kind) => format!("type: {}", kind.description()),
+    },
+    ZExprError::ZBaseErr(_, e) => match e {
+      ZBaseError::InvalidEncoding(_, base) => format!("invalid {} data", base),
+      ZBaseError::NomErr(_, kind) => format!("base: {}", kind.description()),
+    },
+    ZExprError::LinkLength(_, n) => format!("{} byte link digest", n),
+    ZExprError::Invalid(_, e) => e.to_string(),
+    ZExprError::NomErr(_, kind) => kind.description().to_owned(),
+  }
+}
+
+/// A serde `Deserializer` which reads from a borrowed `ZExpr`.
+pub struct Deserializer<'de> {
+  expr: &'de ZExpr,
+}
+
+impl<'de> Deserializer<'de> {
+  pub fn new(expr: &'de ZExpr) -> Self {
+    Deserializer { expr }
+  }
+
+  fn mismatch(&self, expected: &str) -> ZSerdeError {
+    ZSerdeError::Mismatch(String::from(expected), describe(self.expr))
+  }
+
+  fn text(&self) -> Result<&'de str, ZSerdeError> {
+    Ok(<&str>::try_from(self.expr)?)
+  }
+
+  fn symbol(&self) -> Result<&'de str, ZSerdeError> {
+    match self.expr {
+      ZExpr::Atom(ZType::Symbol(_), dat) => std::str::from_utf8(dat)
+        .map_err(|_| ZSerdeError::Message(String::from("invalid utf8 symbol"))),
+      _ => Err(self.mismatch("symbol")),
+    }
+  }
+
+  fn cons(&self) -> Result<&'de [ZExpr], ZSerdeError> {
+    match self.expr {
+      ZExpr::Cons(xs) => Ok(xs),
+      _ => Err(self.mismatch("cons")),
+    }
+  }
+
+  /// A cons whose first element is a name symbol, as written for structs and
+  /// enum variants. Returns the name and the remaining elements.
+  fn named_cons(&self) -> Result<(&'de str, &'de [ZExpr]), ZSerdeError> {
+    match self.cons()?.split_first() {
+      Some((head, rest)) => match Deserializer::new(head).symbol() {
+        Ok(name) => Ok((name, rest)),
+        Err(e) => Err(e.at(PathSegment::Index(0))),
+      },
+      None => Err(ZSerdeError::Mismatch(
+        String::from("named cons"),
+        String::from("empty cons"),
+      )),
+    }
+  }
+}
+
+macro_rules! deserialize_number {
+  ($method:ident, $visit:ident, $ty:ty) => {
+    fn $method<V: Visitor<'de>>(
+      self,
+      visitor: V,
+    ) -> Result<V::Value, ZSerdeError> {
+      visitor.$visit(<$ty>::try_from(self.expr)?)
+    }
+  };
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+  type Error = ZSerdeError;
+
+  fn deserialize_any<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.expr {
+      ZExpr::Atom(ZType::Symbol(_), _) => match self.symbol()? {
+        "true" => visitor.visit_bool(true),
+        "false" => visitor.visit_bool(false),
+        s => visitor.visit_borrowed_str(s),
+      },
+      ZExpr::Atom(ZType::Nat(_), dat) => match be_u128(dat) {
+        Some(n) if n <= u64::MAX as u128 => visitor.visit_u64(n as u64),
+        Some(n) => visitor.visit_u128(n),
+        None => Err(ZSerdeError::OutOfRange(String::from("u128"))),
+      },
+      ZExpr::Atom(ZType::Int(_), dat) => match be_i128(dat) {
+        Some(n) if n as i64 as i128 == n => visitor.visit_i64(n as i64),
+        Some(n) => visitor.visit_i128(n),
+        None => Err(ZSerdeError::OutOfRange(String::from("i128"))),
+      },
+      ZExpr::Atom(ZType::Float(_), dat) if dat.len() == 4 => {
+        self.deserialize_f32(visitor)
+      }
+      ZExpr::Atom(ZType::Float(_), _) => self.deserialize_f64(visitor),
+      ZExpr::Atom(ZType::Text(_), _) => self.deserialize_str(visitor),
+      ZExpr::Atom(ZType::Char(_), _) => self.deserialize_char(visitor),
+      ZExpr::Atom(_, dat) => visitor.visit_borrowed_bytes(dat),
+      ZExpr::Cons(xs) => visitor.visit_seq(SeqAccess::new(xs, 0, &[])),
+    }
+  }
+
+  fn deserialize_bool<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    visitor.visit_bool(bool::try_from(self.expr)?)
+  }
+
+  deserialize_number!(deserialize_u8, visit_u8, u8);
+  deserialize_number!(deserialize_u16, visit_u16, u16);
+  deserialize_number!(deserialize_u32, visit_u32, u32);
+  deserialize_number!(deserialize_u64, visit_u64, u64);
+  deserialize_number!(deserialize_u128, visit_u128, u128);
+  deserialize_number!(deserialize_i8, visit_i8, i8);
+  deserialize_number!(deserialize_i16, visit_i16, i16);
+  deserialize_number!(deserialize_i32, visit_i32, i32);
+  deserialize_number!(deserialize_i64, visit_i64, i64);
+  deserialize_number!(deserialize_i128, visit_i128, i128);
+
+  deserialize_number!(deserialize_f32, visit_f32, f32);
+  deserialize_number!(deserialize_f64, visit_f64, f64);
+  deserialize_number!(deserialize_char, visit_char, char);
+
+  fn deserialize_str<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    visitor.visit_borrowed_str(self.text()?)
+  }
+
+  fn deserialize_string<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    self.deserialize_str(visitor)
+  }
+
+  fn deserialize_bytes<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    visitor.visit_borrowed_bytes(<&[u8]>::try_from(self.expr)?)
+  }
+
+  fn deserialize_byte_buf<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    self.deserialize_bytes(visitor)
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.cons()? {
+      [] => visitor.visit_none(),
+      [x] => visitor
+        .visit_some(Deserializer::new(x))
+        .map_err(|e| e.at(PathSegment::Index(0))),
+      _ => Err(self.mismatch("option")),
+    }
+  }
+
+  fn deserialize_unit<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.cons()? {
+      [] => visitor.visit_unit(),
+      _ => Err(self.mismatch("unit")),
+    }
+  }
+
+  fn deserialize_unit_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.named_cons()? {
+      (_, []) => visitor.visit_unit(),
+      _ => Err(self.mismatch("unit struct")),
+    }
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.named_cons()? {
+      (_, [x]) => visitor
+        .visit_newtype_struct(Deserializer::new(x))
+        .map_err(|e| e.at(PathSegment::Index(1))),
+      _ => Err(self.mismatch("newtype struct")),
+    }
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let xs = self.cons()?;
+    let mut seq = SeqAccess::new(xs, 0, &[]);
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_tuple_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let (_, xs) = self.named_cons()?;
+    let mut seq = SeqAccess::new(xs, 1, &[]);
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+  }
+
+  fn deserialize_map<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let xs = self.cons()?;
+    visitor.visit_map(MapAccess {
+      iter: xs.iter().enumerate(),
+      value: None,
+    })
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let (_, xs) = self.named_cons()?;
+    let mut seq = SeqAccess::new(xs, 1, fields);
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(
+    self,
+    _name: &'static str,
+    _variants: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let (variant, xs) = self.named_cons()?;
+    visitor.visit_enum(EnumAccess { variant, xs })
+  }
+
+  fn deserialize_identifier<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.expr {
+      ZExpr::Atom(ZType::Text(_), _) => self.deserialize_str(visitor),
+      _ => visitor.visit_borrowed_str(self.symbol()?),
+    }
+  }
+
+  fn deserialize_ignored_any<V: Visitor<'de>>(
+    self,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    visitor.visit_unit()
+  }
+}
+
+/// Walks the elements of a cons, tagging errors with their position. `offset`
+/// is the index of the first element within the cons, and `fields` names the
+/// elements of a struct.
+struct SeqAccess<'de> {
+  xs: &'de [ZExpr],
+  offset: usize,
+  fields: &'static [&'static str],
+  next: usize,
+}
+
+impl<'de> SeqAccess<'de> {
+  fn new(
+    xs: &'de [ZExpr],
+    offset: usize,
+    fields: &'static [&'static str],
+  ) -> Self {
+    SeqAccess {
+      xs,
+      offset,
+      fields,
+      next: 0,
+    }
+  }
+
+  fn segment(&self, i: usize) -> PathSegment {
+    match self.fields.get(i) {
+      Some(field) => PathSegment::Field(field),
+      None => PathSegment::Index(self.offset + i),
+    }
+  }
+
+  fn end(&self) -> Result<(), ZSerdeError> {
+    if self.next == self.xs.len() {
+      Ok(())
+    } else {
+      let expected = format!("{} elements", self.offset + self.next);
+      Err(de::Error::invalid_length(
+        self.offset + self.xs.len(),
+        &expected.as_str(),
+      ))
+    }
+  }
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+  type Error = ZSerdeError;
+
+  fn next_element_seed<T: DeserializeSeed<'de>>(
+    &mut self,
+    seed: T,
+  ) -> Result<Option<T::Value>, ZSerdeError> {
+    match self.xs.get(self.next) {
+      Some(x) => {
+        let i = self.next;
+        self.next += 1;
+        seed
+          .deserialize(Deserializer::new(x))
+          .map(Some)
+          .map_err(|e| e.at(self.segment(i)))
+      }
+      None => Ok(None),
+    }
+  }
+
+  fn size_hint(&self) -> Option<usize> {
+    Some(self.xs.len() - self.next)
+  }
+}
+
+/// Walks a cons of `(key value)` pairs.
+struct MapAccess<'de> {
+  iter: std::iter::Enumerate<std::slice::Iter<'de, ZExpr>>,
+  value: Option<(usize, &'de ZExpr)>,
+}
+
+impl<'de> de::MapAccess<'de> for MapAccess<'de> {
+  type Error = ZSerdeError;
+
+  fn next_key_seed<K: DeserializeSeed<'de>>(
+    &mut self,
+    seed: K,
+  ) -> Result<Option<K::Value>, ZSerdeError> {
+    match self.iter.next() {
+      Some((i, ZExpr::Cons(kv))) if kv.len() == 2 => {
+        self.value = Some((i, &kv[1]));
+        seed
+          .deserialize(Deserializer::new(&kv[0]))
+          .map(Some)
+          .map_err(|e| e.at(PathSegment::Index(0)).at(PathSegment::Index(i)))
+      }
+      Some((i, x)) => Err(
+        ZSerdeError::Mismatch(String::from("(key value)"), describe(x))
+          .at(PathSegment::Index(i)),
+      ),
+      None => Ok(None),
+    }
+  }
+
+  fn next_value_seed<V: DeserializeSeed<'de>>(
+    &mut self,
+    seed: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    match self.value.take() {
+      Some((i, x)) => seed
+        .deserialize(Deserializer::new(x))
+        .map_err(|e| e.at(PathSegment::Index(1)).at(PathSegment::Index(i))),
+      None => Err(ZSerdeError::Message(String::from(
+        "next_value_seed called before next_key_seed",
+      ))),
+    }
+  }
+}
+
+/// An enum variant written as `(Variant ...)`.
+struct EnumAccess<'de> {
+  variant: &'de str,
+  xs: &'de [ZExpr],
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+  type Error = ZSerdeError;
+  type Variant = Self;
+
+  fn variant_seed<V: DeserializeSeed<'de>>(
+    self,
+    seed: V,
+  ) -> Result<(V::Value, Self), ZSerdeError> {
+    let de =
+      de::value::BorrowedStrDeserializer::<ZSerdeError>::new(self.variant);
+    let value = seed
+      .deserialize(de)
+      .map_err(|e| e.at(PathSegment::Index(0)))?;
+    Ok((value, self))
+  }
+}
+
+impl<'de> de::VariantAccess<'de> for EnumAccess<'de> {
+  type Error = ZSerdeError;
+
+  fn unit_variant(self) -> Result<(), ZSerdeError> {
+    match self.xs {
+      [] => Ok(()),
+      _ => Err(de::Error::invalid_length(self.xs.len(), &"unit variant")),
+    }
+  }
+
+  fn newtype_variant_seed<T: DeserializeSeed<'de>>(
+    self,
+    seed: T,
+  ) -> Result<T::Value, ZSerdeError> {
+    match self.xs {
+      [x] => seed
+        .deserialize(Deserializer::new(x))
+        .map_err(|e| e.at(PathSegment::Index(1))),
+      _ => Err(de::Error::invalid_length(self.xs.len(), &"newtype variant")),
+    }
+  }
+
+  fn tuple_variant<V: Visitor<'de>>(
+    self,
+    _len: usize,
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let mut seq = SeqAccess::new(self.xs, 1, &[]);
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+  }
+
+  fn struct_variant<V: Visitor<'de>>(
+    self,
+    fields: &'static [&'static str],
+    visitor: V,
+  ) -> Result<V::Value, ZSerdeError> {
+    let mut seq = SeqAccess::new(self.xs, 1, fields);
+    let value = visitor.visit_seq(&mut seq)?;
+    seq.end()?;
+    Ok(value)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zserde::to_vec;
+  use crate::zserde::to_zexpr;
+  use serde_derive::{Deserialize, Serialize};
+  use std::collections::BTreeMap;
+
+  #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+  struct Point {
+    x: u32,
+    y: i16,
+  }
+
+  #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+  struct Meters(f64);
+
+  #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+  enum Shape {
+    Empty,
+    Circle(u8),
+    Line(Point, Point),
+    Rect { w: u64, h: u64 },
+  }
+
+  #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+  struct Doc {
+    name: String,
+    tags: Vec<String>,
+    shapes: Vec<Shape>,
+    scale: Option<Meters>,
+    index: BTreeMap<String, i128>,
+    flag: bool,
+    init: char,
+    unit: (),
+  }
+
+  fn doc() -> Doc {
+    let mut index = BTreeMap::new();
+    index.insert(String::from("min"), i128::MIN);
+    index.insert(String::from("neg"), -1);
+    Doc {
+      name: String::from("doc"),
+      tags: vec![String::from("a"), String::from("b")],
+      shapes: vec![
+        Shape::Empty,
+        Shape::Circle(255),
+        Shape::Line(Point { x: 0, y: -3 }, Point { x: u32::MAX, y: 3 }),
+        Shape::Rect { w: 1, h: u64::MAX },
+      ],
+      scale: Some(Meters(1.5)),
+      index,
+      flag: true,
+      init: 'λ',
+      unit: (),
+    }
+  }
+
+  #[test]
+  fn de_roundtrip() {
+    let d = doc();
+    assert_eq!(from_slice::<Doc>(&to_vec(&d).unwrap()), Ok(d.clone()));
+    let text = format!("{}", to_zexpr(&d).unwrap());
+    assert_eq!(from_str::<Doc>(&text), Ok(d));
+  }
+
+  #[test]
+  fn de_integers() {
+    let x = ZExpr::Atom(ZType::Nat(None), vec![0, 0, 1]);
+    assert_eq!(from_zexpr::<u8>(&x), Ok(1));
+    let x = ZExpr::Atom(ZType::Nat(None), vec![1, 0]);
+    assert_eq!(
+      from_zexpr::<u8>(&x),
+      Err(ZSerdeError::OutOfRange(String::from("u8")))
+    );
+    assert_eq!(from_zexpr::<u16>(&x), Ok(256));
+    let x = ZExpr::Atom(ZType::Int(None), vec![0xff, 0xff, 0x80]);
+    assert_eq!(from_zexpr::<i8>(&x), Ok(-128));
+    let x = ZExpr::Atom(ZType::Int(None), vec![0xff, 0x7f]);
+    assert_eq!(from_zexpr::<i16>(&x), Ok(-129));
+    assert_eq!(
+      from_zexpr::<i8>(&x),
+      Err(ZSerdeError::OutOfRange(String::from("i8")))
+    );
+  }
+
+  #[test]
+  fn de_type_mismatch_path() {
+    let mut x = to_zexpr(&doc()).unwrap();
+    if let ZExpr::Cons(doc) = &mut x {
+      if let ZExpr::Cons(shapes) = &mut doc[3] {
+        if let ZExpr::Cons(line) = &mut shapes[2] {
+          if let ZExpr::Cons(point) = &mut line[2] {
+            point[1] = to_zexpr("zero").unwrap();
+          }
+        }
+      }
+    }
+    let err = from_zexpr::<Doc>(&x).unwrap_err();
+    assert_eq!(
+      err,
+      ZSerdeError::Path(
+        vec![
+          PathSegment::Field("shapes"),
+          PathSegment::Index(2),
+          PathSegment::Index(2),
+          PathSegment::Field("x"),
+        ],
+        Box::new(ZSerdeError::Mismatch(
+          String::from("nat"),
+          String::from("text")
+        ))
+      )
+    );
+    assert_eq!(
+      format!("{}", err),
+      "expected nat, found text at .shapes[2][2].x"
+    );
+  }
+
+  #[test]
+  fn de_rejects_wrong_shapes() {
+    let x = to_zexpr(&Point { x: 1, y: 2 }).unwrap();
+    assert!(from_zexpr::<(u32, i16)>(&x).is_err());
+    assert!(from_zexpr::<Shape>(&x).is_err());
+    assert_eq!(
+      from_zexpr::<u32>(&x),
+      Err(ZSerdeError::Mismatch(
+        String::from("nat"),
+        String::from("cons")
+      ))
+    );
+    let bytes = to_vec(&1u8).unwrap();
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert_eq!(from_slice::<u8>(&bytes), Ok(1));
+    assert_eq!(
+      from_slice::<u8>(&trailing),
+      Err(ZSerdeError::TrailingInput(1))
+    );
+    // errors give the offset, not the rest of the input
+    let mut bad = to_vec(&"x".repeat(1 << 16)).unwrap();
+    bad[1] = 0x0d;
+    assert_eq!(
+      from_slice::<String>(&bad),
+      Err(ZSerdeError::Deserial(
+        2,
+        String::from("invalid type code [0d]")
+      ))
+    );
+    let text = format!("(vy:nat vy:nat9 {})", "vy:nat ".repeat(1 << 12));
+    assert_eq!(
+      from_str::<(u8, u8)>(&text),
+      Err(ZSerdeError::Parse(
+        15,
+        String::from("type index 9 isn't a multiple of 8")
+      ))
+    );
+  }
+}
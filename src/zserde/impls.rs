@@ -0,0 +1,348 @@
+//! `Serialize` and `Deserialize` for the ZExpr value types, so that they can
+//! be embedded in any serde data format.
+//!
+//! All three types are written as externally tagged enums, the same shape
+//! `#[derive(Serialize)]` would give them. In JSON, for example:
+//!
+//! ```text
+//! ZBase::Z32                           "Z32"
+//! ZType::Int(Some(8))                  {"Int":8}
+//! ZType::Text(None)                    {"Text":null}
+//! ZExpr::Atom(ZType::Nat(None), [1])   {"Atom":[{"Nat":null},[1]]}
+//! ZExpr::Cons(vec![])                  {"Cons":[]}
+//! ```
+
+use core::fmt;
+use serde::de::{self, Deserialize, Deserializer, EnumAccess, VariantAccess};
+use serde::ser::{Serialize, SerializeTupleVariant, Serializer};
+
+use crate::zbase::ZBase;
+use crate::ztype::{ExtCode, ZType, BUILTINS};
+use crate::ZExpr;
+
+const ZEXPR_VARIANTS: &[&str] = &["Atom", "Cons"];
+
+/// Reads an enum variant tag, given either as a name or as an index.
+struct VariantTag {
+  variants: &'static [&'static str],
+}
+
+impl<'de> de::DeserializeSeed<'de> for VariantTag {
+  type Value = usize;
+
+  fn deserialize<D: Deserializer<'de>>(self, d: D) -> Result<usize, D::Error> {
+    d.deserialize_identifier(self)
+  }
+}
+
+impl<'de> de::Visitor<'de> for VariantTag {
+  type Value = usize;
+
+  fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "one of {:?}", self.variants)
+  }
+
+  fn visit_u64<E: de::Error>(self, v: u64) -> Result<usize, E> {
+    if (v as usize) < self.variants.len() {
+      Ok(v as usize)
+    } else {
+      Err(E::invalid_value(de::Unexpected::Unsigned(v), &self))
+    }
+  }
+
+  fn visit_str<E: de::Error>(self, v: &str) -> Result<usize, E> {
+    match self.variants.iter().position(|x| *x == v) {
+      Some(i) => Ok(i),
+      None => Err(E::unknown_variant(v, self.variants)),
+    }
+  }
+
+  fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<usize, E> {
+    match std::str::from_utf8(v) {
+      Ok(s) => self.visit_str(s),
+      Err(_) => Err(E::invalid_value(de::Unexpected::Bytes(v), &self)),
+    }
+  }
+}
+
+impl Serialize for ZBase {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    let i = ZBase::ALL.iter().position(|x| x == self).unwrap();
+    s.serialize_unit_variant("ZBase", i as u32, ZBase::VARIANTS[i])
+  }
+}
+
+impl<'de> Deserialize<'de> for ZBase {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    struct ZBaseVisitor;
+
+    impl<'de> de::Visitor<'de> for ZBaseVisitor {
+      type Value = ZBase;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a ZBase")
+      }
+
+      fn visit_enum<A: EnumAccess<'de>>(self, a: A) -> Result<ZBase, A::Error> {
+        let (i, v) = a.variant_seed(VariantTag {
+          variants: ZBase::VARIANTS,
+        })?;
+        v.unit_variant()?;
+        Ok(ZBase::ALL[i])
+      }
+    }
+
+    d.deserialize_enum("ZBase", ZBase::VARIANTS, ZBaseVisitor)
+  }
+}
+
+impl Serialize for ZType {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    if let Self::Ext(code, len) = self {
+      let i = BUILTINS.len();
+      let mut tv =
+        s.serialize_tuple_variant("ZType", i as u32, ZType::VARIANTS[i], 2)?;
+      tv.serialize_field(&code.get())?;
+      tv.serialize_field(len)?;
+      return tv.end();
+    }
+    let i = BUILTINS
+      .iter()
+      .position(|(_, code, _)| u64::from(*code) == self.code())
+      .unwrap();
+    let len = self.length();
+    s.serialize_newtype_variant("ZType", i as u32, ZType::VARIANTS[i], &len)
+  }
+}
+
+impl<'de> Deserialize<'de> for ZType {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    struct ZTypeVisitor;
+
+    impl<'de> de::Visitor<'de> for ZTypeVisitor {
+      type Value = ZType;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a ZType")
+      }
+
+      fn visit_enum<A: EnumAccess<'de>>(self, a: A) -> Result<ZType, A::Error> {
+        let (i, v) = a.variant_seed(VariantTag {
+          variants: ZType::VARIANTS,
+        })?;
+        match BUILTINS.get(i) {
+          Some((_, _, typ)) => Ok(typ(v.newtype_variant()?)),
+          None => v.tuple_variant(2, ExtVisitor),
+        }
+      }
+    }
+
+    struct ExtVisitor;
+
+    impl<'de> de::Visitor<'de> for ExtVisitor {
+      type Value = ZType;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "an extension ZType")
+      }
+
+      fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut a: A,
+      ) -> Result<ZType, A::Error> {
+        let code: u64 = match a.next_element()? {
+          Some(code) => code,
+          None => return Err(de::Error::invalid_length(0, &self)),
+        };
+        let code = match ExtCode::new(code) {
+          Some(code) => code,
+          None => {
+            let code = de::Unexpected::Unsigned(code);
+            return Err(de::Error::invalid_value(code, &"a code above 0xff"));
+          }
+        };
+        let len: Option<u64> = match a.next_element()? {
+          Some(len) => len,
+          None => return Err(de::Error::invalid_length(1, &self)),
+        };
+        Ok(ZType::Ext(code, len))
+      }
+    }
+
+    d.deserialize_enum("ZType", ZType::VARIANTS, ZTypeVisitor)
+  }
+}
+
+/// Atom payloads, written with `serialize_bytes`.
+struct Payload<'a>(&'a [u8]);
+
+impl<'a> Serialize for Payload<'a> {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_bytes(self.0)
+  }
+}
+
+/// Reads atom payloads from formats which write bytes as a sequence, as well
+/// as from those with a native bytes type.
+struct PayloadBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for PayloadBuf {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    struct PayloadVisitor;
+
+    impl<'de> de::Visitor<'de> for PayloadVisitor {
+      type Value = PayloadBuf;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "atom bytes")
+      }
+
+      fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<PayloadBuf, E> {
+        Ok(PayloadBuf(v.to_vec()))
+      }
+
+      fn visit_byte_buf<E: de::Error>(
+        self,
+        v: Vec<u8>,
+      ) -> Result<PayloadBuf, E> {
+        Ok(PayloadBuf(v))
+      }
+
+      fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut a: A,
+      ) -> Result<PayloadBuf, A::Error> {
+        let mut v = Vec::with_capacity(a.size_hint().unwrap_or(0).min(4096));
+        while let Some(b) = a.next_element()? {
+          v.push(b);
+        }
+        Ok(PayloadBuf(v))
+      }
+    }
+
+    d.deserialize_byte_buf(PayloadVisitor)
+  }
+}
+
+impl Serialize for ZExpr {
+  fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+    match self {
+      Self::Atom(typ, dat) => {
+        let mut tv = s.serialize_tuple_variant("ZExpr", 0, "Atom", 2)?;
+        tv.serialize_field(typ)?;
+        tv.serialize_field(&Payload(dat))?;
+        tv.end()
+      }
+      Self::Cons(xs) => s.serialize_newtype_variant("ZExpr", 1, "Cons", xs),
+    }
+  }
+}
+
+impl<'de> Deserialize<'de> for ZExpr {
+  fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+    struct ZExprVisitor;
+
+    impl<'de> de::Visitor<'de> for ZExprVisitor {
+      type Value = ZExpr;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a ZExpr")
+      }
+
+      fn visit_enum<A: EnumAccess<'de>>(self, a: A) -> Result<ZExpr, A::Error> {
+        let (i, v) = a.variant_seed(VariantTag {
+          variants: ZEXPR_VARIANTS,
+        })?;
+        match i {
+          0 => v.tuple_variant(2, AtomVisitor),
+          _ => Ok(ZExpr::Cons(v.newtype_variant()?)),
+        }
+      }
+    }
+
+    struct AtomVisitor;
+
+    impl<'de> de::Visitor<'de> for AtomVisitor {
+      type Value = ZExpr;
+
+      fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a ZExpr atom")
+      }
+
+      fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut a: A,
+      ) -> Result<ZExpr, A::Error> {
+        let typ: ZType = match a.next_element()? {
+          Some(typ) => typ,
+          None => return Err(de::Error::invalid_length(0, &self)),
+        };
+        let dat: PayloadBuf = match a.next_element()? {
+          Some(dat) => dat,
+          None => return Err(de::Error::invalid_length(1, &self)),
+        };
+        Ok(ZExpr::Atom(typ, dat.0))
+      }
+    }
+
+    d.deserialize_enum("ZExpr", ZEXPR_VARIANTS, ZExprVisitor)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::zbase::ZBase;
+  use crate::zserde::{from_zexpr, to_zexpr};
+  use crate::ztype::{ExtCode, ZType};
+  use crate::ZExpr;
+
+  #[test]
+  fn serde_json_repr() {
+    let x = ZExpr::Cons(vec![
+      ZExpr::Atom(ZType::Nat(None), vec![1]),
+      ZExpr::Atom(ZType::Int(Some(u64::MAX)), vec![]),
+    ]);
+    let json = serde_json::to_string(&x).unwrap();
+    assert_eq!(
+      json,
+      r#"{"Cons":[{"Atom":[{"Nat":null},[1]]},{"Atom":[{"Int":18446744073709551615},[]]}]}"#
+    );
+    assert_eq!(serde_json::from_str::<ZExpr>(&json).unwrap(), x);
+    assert_eq!(serde_json::to_string(&ZBase::Z58).unwrap(), r#""Z58""#);
+    assert_eq!(
+      serde_json::from_str::<ZBase>(r#""Z58""#).unwrap(),
+      ZBase::Z58
+    );
+    let ext = ZType::Ext(ExtCode::new(0x1f00).unwrap(), None);
+    assert_eq!(
+      serde_json::to_string(&ext).unwrap(),
+      r#"{"Ext":[7936,null]}"#
+    );
+    // a 1 byte code would serialize as a built-in type
+    assert!(serde_json::from_str::<ZType>(r#"{"Ext":[2,null]}"#).is_err());
+  }
+
+  #[quickcheck]
+  fn serde_json_zexpr(x: ZExpr) -> bool {
+    let json = serde_json::to_string(&x).unwrap();
+    serde_json::from_str::<ZExpr>(&json).unwrap() == x
+  }
+
+  #[quickcheck]
+  fn serde_json_ztype(x: ZType) -> bool {
+    let json = serde_json::to_string(&x).unwrap();
+    serde_json::from_str::<ZType>(&json).unwrap() == x
+  }
+
+  #[quickcheck]
+  fn serde_json_zbase(x: ZBase) -> bool {
+    let json = serde_json::to_string(&x).unwrap();
+    serde_json::from_str::<ZBase>(&json).unwrap() == x
+  }
+
+  #[quickcheck]
+  fn serde_zexpr_zexpr(x: ZExpr, t: ZType) -> bool {
+    let y = ZExpr::Cons(vec![x, ZExpr::Atom(t, vec![])]);
+    from_zexpr::<ZExpr>(&to_zexpr(&y).unwrap()).unwrap() == y
+  }
+}
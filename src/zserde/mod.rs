@@ -0,0 +1,134 @@
+//! A serde data format for ZExpr.
+//!
+//! Rust values are mapped onto the ZExpr data model as follows:
+//!
+//! | serde type          | ZExpr                                  |
+//! |---------------------|----------------------------------------|
+//! | `bool`              | `true:symbol` or `false:symbol`        |
+//! | `u8` .. `u128`      | `nat8` .. `nat128`, big-endian         |
+//! | `i8` .. `i128`      | `int8` .. `int128`, two's complement   |
+//! | `f32`, `f64`        | `float32`, `float64`, IEEE 754         |
+//! | `char`              | `char32`, the big-endian scalar value  |
+//! | `str`               | `text`, utf8 encoded                   |
+//! | `bytes`             | `bytes`                                |
+//! | `()`, `None`        | `()`                                   |
+//! | `Some(x)`           | `(x)`                                  |
+//! | seq, tuple          | `(x y z ...)`                          |
+//! | map                 | `((k v) (k v) ...)`                    |
+//! | unit struct         | `(Name)`                               |
+//! | newtype struct      | `(Name x)`                             |
+//! | tuple struct        | `(Name x y ...)`                       |
+//! | struct              | `(Name field0 field1 ...)`             |
+//! | enum variant        | `(Variant ...)`, as for structs        |
+//!
+//! Struct fields are written positionally in declaration order, and struct
+//! and variant names are written as `symbol` atoms.
+//!
+//! Deserialization checks the `ZType` of every atom against the type the
+//! visitor asks for. Integers of any width are accepted as long as the value
+//! fits the requested Rust type.
+
+pub mod de;
+mod impls;
+pub mod ser;
+
+use core::fmt;
+
+use crate::zconv::ZConvError;
+
+pub use de::{from_slice, from_str, from_zexpr, Deserializer};
+pub use ser::{to_vec, to_zexpr, Serializer};
+
+/// A step into a `ZExpr` tree: either the index of a cons element or the name
+/// of the struct field stored there.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum PathSegment {
+  Index(usize),
+  Field(&'static str),
+}
+
+impl fmt::Display for PathSegment {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Index(i) => write!(f, "[{}]", i),
+      Self::Field(name) => write!(f, ".{}", name),
+    }
+  }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ZSerdeError {
+  Message(String),
+  /// An atom or cons did not have the expected shape: (expected, found)
+  Mismatch(String, String),
+  /// A number does not fit the named Rust type
+  OutOfRange(String),
+  /// Bytes or characters left over after the expression
+  TrailingInput(usize),
+  /// Text which doesn't parse: (offset of the error, what went wrong)
+  Parse(usize, String),
+  /// Bytes which don't decode: (offset of the error, what went wrong)
+  Deserial(usize, String),
+  /// An error raised at the given path inside the expression
+  Path(Vec<PathSegment>, Box<ZSerdeError>),
+}
+
+impl ZSerdeError {
+  /// Record that this error happened beneath `seg`.
+  pub fn at(self, seg: PathSegment) -> Self {
+    match self {
+      Self::Path(mut path, err) => {
+        path.insert(0, seg);
+        Self::Path(path, err)
+      }
+      err => Self::Path(vec![seg], Box::new(err)),
+    }
+  }
+}
+
+impl fmt::Display for ZSerdeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Message(msg) => write!(f, "{}", msg),
+      Self::Mismatch(expected, found) => {
+        write!(f, "expected {}, found {}", expected, found)
+      }
+      Self::OutOfRange(ty) => write!(f, "number out of range for {}", ty),
+      Self::TrailingInput(n) => write!(f, "{} trailing bytes of input", n),
+      Self::Parse(pos, e) => write!(f, "parse error at byte {}: {}", pos, e),
+      Self::Deserial(pos, e) => {
+        write!(f, "deserialization error at byte {}: {}", pos, e)
+      }
+      Self::Path(path, err) => {
+        write!(f, "{} at ", err)?;
+        path.iter().try_for_each(|seg| write!(f, "{}", seg))
+      }
+    }
+  }
+}
+
+impl std::error::Error for ZSerdeError {}
+
+impl From<ZConvError> for ZSerdeError {
+  fn from(e: ZConvError) -> Self {
+    match e {
+      ZConvError::Mismatch(expected, found) => {
+        ZSerdeError::Mismatch(String::from(expected), found)
+      }
+      ZConvError::OutOfRange(ty) => ZSerdeError::OutOfRange(String::from(ty)),
+      e @ ZConvError::InvalidUtf8(_) => ZSerdeError::Message(e.to_string()),
+    }
+  }
+}
+
+impl serde::ser::Error for ZSerdeError {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    ZSerdeError::Message(msg.to_string())
+  }
+}
+
+impl serde::de::Error for ZSerdeError {
+  fn custom<T: fmt::Display>(msg: T) -> Self {
+    ZSerdeError::Message(msg.to_string())
+  }
+}
@@ -0,0 +1,470 @@
+use serde::ser::{self, Serialize};
+
+use crate::zserde::ZSerdeError;
+use crate::ztype::ZType;
+use crate::ZExpr;
+
+/// Serialize a value into a `ZExpr` tree.
+pub fn to_zexpr<T: ?Sized + Serialize>(
+  value: &T,
+) -> Result<ZExpr, ZSerdeError> {
+  value.serialize(Serializer)
+}
+
+/// Serialize a value into the binary format produced by `ZExpr::serialize`.
+pub fn to_vec<T: ?Sized + Serialize>(
+  value: &T,
+) -> Result<Vec<u8>, ZSerdeError> {
+  Ok(to_zexpr(value)?.serialize())
+}
+
+pub(crate) fn symbol(name: &str) -> ZExpr {
+  ZExpr::Atom(ZType::Symbol(None), name.as_bytes().to_vec())
+}
+
+/// A serde `Serializer` whose output is a `ZExpr`.
+pub struct Serializer;
+
+impl ser::Serializer for Serializer {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  type SerializeSeq = SerializeCons;
+  type SerializeTuple = SerializeCons;
+  type SerializeTupleStruct = SerializeCons;
+  type SerializeTupleVariant = SerializeCons;
+  type SerializeMap = SerializeMap;
+  type SerializeStruct = SerializeCons;
+  type SerializeStructVariant = SerializeCons;
+
+  fn serialize_bool(self, v: bool) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_i8(self, v: i8) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_i16(self, v: i16) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_i32(self, v: i32) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_i64(self, v: i64) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_i128(self, v: i128) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_u8(self, v: u8) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_u16(self, v: u16) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_u32(self, v: u32) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_u64(self, v: u64) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_u128(self, v: u128) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_f32(self, v: f32) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_f64(self, v: f64) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_char(self, v: char) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_str(self, v: &str) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_bytes(self, v: &[u8]) -> Result<ZExpr, ZSerdeError> {
+    Ok(v.into())
+  }
+
+  fn serialize_none(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![]))
+  }
+
+  fn serialize_some<T: ?Sized + Serialize>(
+    self,
+    value: &T,
+  ) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![value.serialize(Serializer)?]))
+  }
+
+  fn serialize_unit(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![]))
+  }
+
+  fn serialize_unit_struct(
+    self,
+    name: &'static str,
+  ) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![symbol(name)]))
+  }
+
+  fn serialize_unit_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+  ) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![symbol(variant)]))
+  }
+
+  fn serialize_newtype_struct<T: ?Sized + Serialize>(
+    self,
+    name: &'static str,
+    value: &T,
+  ) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![
+      symbol(name),
+      value.serialize(Serializer)?,
+    ]))
+  }
+
+  fn serialize_newtype_variant<T: ?Sized + Serialize>(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    value: &T,
+  ) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(vec![
+      symbol(variant),
+      value.serialize(Serializer)?,
+    ]))
+  }
+
+  fn serialize_seq(
+    self,
+    len: Option<usize>,
+  ) -> Result<SerializeCons, ZSerdeError> {
+    Ok(SerializeCons::new(None, len.unwrap_or(0)))
+  }
+
+  fn serialize_tuple(self, len: usize) -> Result<SerializeCons, ZSerdeError> {
+    Ok(SerializeCons::new(None, len))
+  }
+
+  fn serialize_tuple_struct(
+    self,
+    name: &'static str,
+    len: usize,
+  ) -> Result<SerializeCons, ZSerdeError> {
+    Ok(SerializeCons::new(Some(name), len))
+  }
+
+  fn serialize_tuple_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<SerializeCons, ZSerdeError> {
+    Ok(SerializeCons::new(Some(variant), len))
+  }
+
+  fn serialize_map(
+    self,
+    len: Option<usize>,
+  ) -> Result<SerializeMap, ZSerdeError> {
+    Ok(SerializeMap {
+      entries: Vec::with_capacity(len.unwrap_or(0)),
+      key: None,
+    })
+  }
+
+  fn serialize_struct(
+    self,
+    name: &'static str,
+    len: usize,
+  ) -> Result<SerializeCons, ZSerdeError> {
+    Ok(SerializeCons::new(Some(name), len))
+  }
+
+  fn serialize_struct_variant(
+    self,
+    _name: &'static str,
+    _variant_index: u32,
+    variant: &'static str,
+    len: usize,
+  ) -> Result<SerializeCons, ZSerdeError> {
+    Ok(SerializeCons::new(Some(variant), len))
+  }
+}
+
+/// Collects the elements of a sequence, tuple, struct or variant into a
+/// `ZExpr::Cons`, optionally headed by a name symbol.
+pub struct SerializeCons {
+  xs: Vec<ZExpr>,
+}
+
+impl SerializeCons {
+  fn new(head: Option<&str>, len: usize) -> Self {
+    let mut xs = Vec::with_capacity(len + 1);
+    if let Some(name) = head {
+      xs.push(symbol(name));
+    }
+    SerializeCons { xs }
+  }
+
+  fn push<T: ?Sized + Serialize>(&mut self, x: &T) -> Result<(), ZSerdeError> {
+    self.xs.push(x.serialize(Serializer)?);
+    Ok(())
+  }
+}
+
+impl ser::SerializeSeq for SerializeCons {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_element<T: ?Sized + Serialize>(
+    &mut self,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.xs))
+  }
+}
+
+impl ser::SerializeTuple for SerializeCons {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_element<T: ?Sized + Serialize>(
+    &mut self,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.xs))
+  }
+}
+
+impl ser::SerializeTupleStruct for SerializeCons {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_field<T: ?Sized + Serialize>(
+    &mut self,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.xs))
+  }
+}
+
+impl ser::SerializeTupleVariant for SerializeCons {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_field<T: ?Sized + Serialize>(
+    &mut self,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.xs))
+  }
+}
+
+impl ser::SerializeStruct for SerializeCons {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_field<T: ?Sized + Serialize>(
+    &mut self,
+    _key: &'static str,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.xs))
+  }
+}
+
+impl ser::SerializeStructVariant for SerializeCons {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_field<T: ?Sized + Serialize>(
+    &mut self,
+    _key: &'static str,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.push(value)
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.xs))
+  }
+}
+
+/// Collects map entries into a `ZExpr::Cons` of `(key value)` pairs.
+pub struct SerializeMap {
+  entries: Vec<ZExpr>,
+  key: Option<ZExpr>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+  type Ok = ZExpr;
+  type Error = ZSerdeError;
+
+  fn serialize_key<T: ?Sized + Serialize>(
+    &mut self,
+    key: &T,
+  ) -> Result<(), ZSerdeError> {
+    self.key = Some(key.serialize(Serializer)?);
+    Ok(())
+  }
+
+  fn serialize_value<T: ?Sized + Serialize>(
+    &mut self,
+    value: &T,
+  ) -> Result<(), ZSerdeError> {
+    match self.key.take() {
+      Some(key) => {
+        self
+          .entries
+          .push(ZExpr::Cons(vec![key, value.serialize(Serializer)?]));
+        Ok(())
+      }
+      None => Err(ZSerdeError::Message(String::from(
+        "serialize_value called before serialize_key",
+      ))),
+    }
+  }
+
+  fn end(self) -> Result<ZExpr, ZSerdeError> {
+    Ok(ZExpr::Cons(self.entries))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+  use serde_derive::Serialize;
+  use std::collections::BTreeMap;
+
+  fn sym(s: &str) -> ZExpr {
+    ZExpr::Atom(Symbol(None), s.as_bytes().to_vec())
+  }
+
+  #[derive(Serialize)]
+  struct Point {
+    x: u32,
+    y: i16,
+  }
+
+  #[derive(Serialize)]
+  enum Shape {
+    Empty,
+    Circle(u8),
+    Line(Point, Point),
+    Rect { w: u64, h: u64 },
+  }
+
+  #[test]
+  fn ser_primitives() {
+    assert_eq!(to_zexpr(&true), Ok(sym("true")));
+    assert_eq!(
+      to_zexpr(&0x0102u16),
+      Ok(ZExpr::Atom(Nat(Some(2)), vec![1, 2]))
+    );
+    assert_eq!(to_zexpr(&-2i8), Ok(ZExpr::Atom(Int(Some(1)), vec![0xfe])));
+    assert_eq!(
+      to_zexpr(&1.0f32),
+      Ok(ZExpr::Atom(Float(Some(4)), vec![0x3f, 0x80, 0, 0]))
+    );
+    assert_eq!(
+      to_zexpr(&'a'),
+      Ok(ZExpr::Atom(Char(Some(4)), vec![0, 0, 0, 0x61]))
+    );
+    assert_eq!(to_zexpr("hi"), Ok(ZExpr::Atom(Text(None), b"hi".to_vec())));
+    assert_eq!(to_zexpr(&()), Ok(ZExpr::Cons(vec![])));
+    assert_eq!(to_zexpr(&None::<u8>), Ok(ZExpr::Cons(vec![])));
+    assert_eq!(
+      to_zexpr(&Some(1u8)),
+      Ok(ZExpr::Cons(vec![ZExpr::Atom(Nat(Some(1)), vec![1])]))
+    );
+  }
+
+  #[test]
+  fn ser_structs_and_enums() {
+    let x = ZExpr::Atom(Nat(Some(4)), vec![0, 0, 0, 1]);
+    let y = ZExpr::Atom(Int(Some(2)), vec![0xff, 0xff]);
+    let point = ZExpr::Cons(vec![sym("Point"), x, y]);
+    assert_eq!(to_zexpr(&Point { x: 1, y: -1 }), Ok(point.clone()));
+    assert_eq!(to_zexpr(&Shape::Empty), Ok(ZExpr::Cons(vec![sym("Empty")])));
+    assert_eq!(
+      to_zexpr(&Shape::Circle(3)),
+      Ok(ZExpr::Cons(vec![
+        sym("Circle"),
+        ZExpr::Atom(Nat(Some(1)), vec![3])
+      ]))
+    );
+    assert_eq!(
+      to_zexpr(&Shape::Line(Point { x: 1, y: -1 }, Point { x: 1, y: -1 })),
+      Ok(ZExpr::Cons(vec![sym("Line"), point.clone(), point]))
+    );
+    let w = ZExpr::Atom(Nat(Some(8)), vec![0, 0, 0, 0, 0, 0, 0, 2]);
+    assert_eq!(
+      to_zexpr(&Shape::Rect { w: 2, h: 2 }),
+      Ok(ZExpr::Cons(vec![sym("Rect"), w.clone(), w]))
+    );
+  }
+
+  #[test]
+  fn ser_maps_and_bytes() {
+    let mut map = BTreeMap::new();
+    map.insert(String::from("a"), vec![1u8, 2]);
+    let key = ZExpr::Atom(Text(None), b"a".to_vec());
+    let val = ZExpr::Cons(vec![
+      ZExpr::Atom(Nat(Some(1)), vec![1]),
+      ZExpr::Atom(Nat(Some(1)), vec![2]),
+    ]);
+    assert_eq!(
+      to_zexpr(&map),
+      Ok(ZExpr::Cons(vec![ZExpr::Cons(vec![key, val])]))
+    );
+    let bytes = to_vec(&Point { x: 1, y: -1 }).unwrap();
+    assert_eq!(
+      ZExpr::deserialize(&bytes).unwrap().1,
+      to_zexpr(&Point { x: 1, y: -1 }).unwrap()
+    );
+  }
+}
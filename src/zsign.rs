@@ -0,0 +1,262 @@
+//! Ed25519 signatures over the links of `ZExpr`s.
+//!
+//! A signature covers the serialized link atom of an expression rather than
+//! the expression itself, so it commits to the hash algorithm as well as the
+//! digest, and a large expression only has to be hashed once to be checked.
+//! Keys and signatures are carried as `pubkey256` and `signature512` atoms.
+//!
+//! Only expressions which pass `ZExpr::validate` are signed or accepted as
+//! signed, so an atom whose data breaks its type can't stand in for another.
+//!
+//! A signed bundle is `(<x> <pubkey> <signature>)`. Opening one checks the
+//! signature against the key inside it; whether that key is trusted is up to
+//! the caller.
+
+use core::fmt;
+
+use ed25519_dalek::Signer;
+pub use ed25519_dalek::{Signature, SigningKey, VerifyingKey};
+
+use crate::zlink::ZLink;
+use crate::ztype::ZType;
+use crate::zvalid::Invalid;
+use crate::ZExpr;
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum ZSignError {
+  /// The expression isn't a cons of an expression, a key and a signature
+  NotABundle,
+  /// The key atom isn't a valid ed25519 public key
+  BadKey,
+  /// The signature doesn't match the expression and key
+  BadSignature,
+  /// The expression signed, or to be signed, fails `ZExpr::validate`: (the
+  /// first invalid atom)
+  Invalid(Invalid),
+}
+
+impl fmt::Display for ZSignError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::NotABundle => write!(f, "not a signed bundle"),
+      Self::BadKey => write!(f, "invalid ed25519 public key"),
+      Self::BadSignature => write!(f, "signature check failed"),
+      Self::Invalid(e) => write!(f, "invalid expression: {}", e),
+    }
+  }
+}
+
+impl std::error::Error for ZSignError {}
+
+/// The bytes signed for `link`: its serialized atom.
+fn message(link: &ZLink) -> Vec<u8> {
+  link.to_zexpr().serialize()
+}
+
+pub fn sign(key: &SigningKey, link: &ZLink) -> Signature {
+  key.sign(&message(link))
+}
+
+/// Whether `sig` is `key`'s signature of `link`. Rejects the malleable
+/// signatures which plain ed25519 verification lets through.
+pub fn verify(key: &VerifyingKey, link: &ZLink, sig: &Signature) -> bool {
+  key.verify_strict(&message(link), sig).is_ok()
+}
+
+/// `x`'s blake3 link, if `x` is valid.
+fn signed_link(x: &ZExpr) -> Result<ZLink, ZSignError> {
+  match x.validate() {
+    Ok(()) => Ok(ZLink::of(x).expect("valid atoms have their types' lengths")),
+    Err(mut invalid) => Err(ZSignError::Invalid(invalid.remove(0))),
+  }
+}
+
+/// Sign the blake3 link of `x`, failing with `Invalid` unless `x` is valid.
+pub fn sign_expr(key: &SigningKey, x: &ZExpr) -> Result<Signature, ZSignError> {
+  Ok(sign(key, &signed_link(x)?))
+}
+
+/// Whether `sig` is `key`'s signature of the link of `x`. An invalid
+/// expression has no signatures.
+pub fn verify_expr(key: &VerifyingKey, x: &ZExpr, sig: &Signature) -> bool {
+  match signed_link(x) {
+    Ok(link) => verify(key, &link, sig),
+    Err(_) => false,
+  }
+}
+
+pub fn pubkey_to_zexpr(key: &VerifyingKey) -> ZExpr {
+  ZExpr::Atom(ZType::Pubkey(Some(32)), key.as_bytes().to_vec())
+}
+
+pub fn pubkey_from_zexpr(x: &ZExpr) -> Option<VerifyingKey> {
+  match x {
+    ZExpr::Atom(ZType::Pubkey(Some(32)), dat) if dat.len() == 32 => {
+      let mut bytes = [0u8; 32];
+      bytes.copy_from_slice(dat);
+      VerifyingKey::from_bytes(&bytes).ok()
+    }
+    _ => None,
+  }
+}
+
+pub fn signature_to_zexpr(sig: &Signature) -> ZExpr {
+  ZExpr::Atom(ZType::Signature(Some(64)), sig.to_bytes().to_vec())
+}
+
+pub fn signature_from_zexpr(x: &ZExpr) -> Option<Signature> {
+  match x {
+    ZExpr::Atom(ZType::Signature(Some(64)), dat) if dat.len() == 64 => {
+      let mut bytes = [0u8; 64];
+      bytes.copy_from_slice(dat);
+      Some(Signature::from_bytes(&bytes))
+    }
+    _ => None,
+  }
+}
+
+/// `x` signed by `key`, as `(<x> <pubkey> <signature>)`.
+pub fn bundle(key: &SigningKey, x: &ZExpr) -> Result<ZExpr, ZSignError> {
+  let sig = sign_expr(key, x)?;
+  Ok(ZExpr::Cons(vec![
+    x.clone(),
+    pubkey_to_zexpr(&key.verifying_key()),
+    signature_to_zexpr(&sig),
+  ]))
+}
+
+/// The expression in a signed bundle and the key which signed it, once the
+/// expression is validated and the signature checked.
+pub fn open(bundle: &ZExpr) -> Result<(&ZExpr, VerifyingKey), ZSignError> {
+  let (x, key, sig) = match bundle {
+    ZExpr::Cons(xs) if xs.len() == 3 => (&xs[0], &xs[1], &xs[2]),
+    _ => return Err(ZSignError::NotABundle),
+  };
+  let sig = signature_from_zexpr(sig).ok_or(ZSignError::NotABundle)?;
+  let key = match key {
+    ZExpr::Atom(ZType::Pubkey(_), _) => {
+      pubkey_from_zexpr(key).ok_or(ZSignError::BadKey)?
+    }
+    _ => return Err(ZSignError::NotABundle),
+  };
+  if verify(&key, &signed_link(x)?, &sig) {
+    Ok((x, key))
+  } else {
+    Err(ZSignError::BadSignature)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zlink::HashAlg;
+  use crate::ztype::ZType::*;
+  use crate::zvalid::Violation;
+
+  fn config() -> ZExpr {
+    ZExpr::Cons(vec![
+      ZExpr::Atom(Symbol(None), b"port".to_vec()),
+      ZExpr::Atom(Nat(Some(2)), vec![0x1f, 0x90]),
+    ])
+  }
+
+  #[test]
+  fn zsign_verify() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let public = key.verifying_key();
+    let x = config();
+    let sig = sign_expr(&key, &x).unwrap();
+    assert!(verify_expr(&public, &x, &sig));
+    assert!(verify(&public, &ZLink::of(&x).unwrap(), &sig));
+    assert!(!verify_expr(&public, &ZExpr::Cons(vec![]), &sig));
+    let other = SigningKey::from_bytes(&[8; 32]).verifying_key();
+    assert!(!verify_expr(&other, &x, &sig));
+    // the same digest under another algorithm is another message
+    let sha = ZLink::of_with(HashAlg::Sha256, &x).unwrap();
+    let blake = ZLink::from_bytes(sha.as_bytes()).unwrap();
+    assert!(!verify(&public, &blake, &sign(&key, &sha)));
+
+    assert_eq!(pubkey_from_zexpr(&pubkey_to_zexpr(&public)), Some(public));
+    assert_eq!(signature_from_zexpr(&signature_to_zexpr(&sig)), Some(sig));
+    match pubkey_to_zexpr(&public) {
+      ZExpr::Atom(typ, _) => assert_eq!(format!("{}", typ), "pubkey256"),
+      y => panic!("unexpected {:?}", y),
+    }
+    assert_eq!(
+      signature_from_zexpr(&ZExpr::Atom(Signature(None), vec![])),
+      None
+    );
+  }
+
+  #[test]
+  fn zsign_bundle() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    let x = config();
+    let signed = bundle(&key, &x).unwrap();
+    assert_eq!(open(&signed), Ok((&x, key.verifying_key())));
+    // bundles survive the text syntax
+    let text = format!("{}", signed);
+    assert_eq!(crate::parse(&text).map(|(_, y)| open(&y).is_ok()), Ok(true));
+
+    let forged = match &signed {
+      ZExpr::Cons(xs) => {
+        ZExpr::Cons(vec![ZExpr::Cons(vec![]), xs[1].clone(), xs[2].clone()])
+      }
+      _ => unreachable!(),
+    };
+    assert_eq!(open(&forged), Err(ZSignError::BadSignature));
+    assert_eq!(open(&x), Err(ZSignError::NotABundle));
+    let bad_key = match &signed {
+      ZExpr::Cons(xs) => ZExpr::Cons(vec![
+        x.clone(),
+        // no point on the curve has y = 2
+        ZExpr::Atom(Pubkey(Some(32)), [&[2][..], &[0; 31]].concat()),
+        xs[2].clone(),
+      ]),
+      _ => unreachable!(),
+    };
+    assert_eq!(open(&bad_key), Err(ZSignError::BadKey));
+  }
+
+  #[test]
+  fn zsign_invalid() {
+    let key = SigningKey::from_bytes(&[7; 32]);
+    // serializes as the valid `port` of config(), so shares its link bytes
+    let short = ZExpr::Cons(vec![
+      ZExpr::Atom(Symbol(None), b"port".to_vec()),
+      ZExpr::Atom(Nat(Some(1)), vec![0x1f, 0x90]),
+    ]);
+    assert_eq!(short.serialize(), config().serialize());
+    let invalid =
+      |path, violation| Some(ZSignError::Invalid(Invalid { path, violation }));
+    assert_eq!(
+      sign_expr(&key, &short).err(),
+      invalid(vec![1], Violation::Length(1, 2))
+    );
+    assert_eq!(
+      bundle(&key, &short).err(),
+      invalid(vec![1], Violation::Length(1, 2))
+    );
+    // a signature of the valid twin doesn't carry over
+    let sig = sign_expr(&key, &config()).unwrap();
+    assert!(!verify_expr(&key.verifying_key(), &short, &sig));
+    let forged = ZExpr::Cons(vec![
+      short,
+      pubkey_to_zexpr(&key.verifying_key()),
+      signature_to_zexpr(&sig),
+    ]);
+    assert_eq!(
+      open(&forged).err(),
+      invalid(vec![1], Violation::Length(1, 2))
+    );
+    // nor does one of text which isn't UTF-8
+    let bad = ZExpr::Atom(Text(None), vec![0xff]);
+    let sig = sign(&key, &ZLink::of(&bad).unwrap());
+    let forged = ZExpr::Cons(vec![
+      bad,
+      pubkey_to_zexpr(&key.verifying_key()),
+      signature_to_zexpr(&sig),
+    ]);
+    assert_eq!(open(&forged).err(), invalid(vec![], Violation::Utf8(0)));
+  }
+}
@@ -0,0 +1,466 @@
+//! Places to keep the blocks of a `zdag`, named by their `ZLink`s.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::zdag::{BlockSource, ZDagError};
+use crate::zlink::{HashAlg, ZLink};
+use crate::ZExpr;
+
+/// A set of blocks keyed by the hash of their bytes, blake3 unless a block
+/// was put with another `HashAlg`. Every store is also a `BlockSource`.
+pub trait BlockStore: BlockSource {
+  /// Store `bytes` under their `alg` hash, returning the link to them.
+  /// Putting a block which is already stored does nothing, unless the stored
+  /// copy is corrupt, in which case it's replaced.
+  fn put_with(
+    &mut self,
+    alg: HashAlg,
+    bytes: &[u8],
+  ) -> Result<ZLink, ZDagError>;
+
+  /// Store `bytes` under their blake3 hash.
+  fn put(&mut self, bytes: &[u8]) -> Result<ZLink, ZDagError> {
+    self.put_with(HashAlg::Blake3, bytes)
+  }
+
+  /// The block stored under `link`, failing with `Corrupt` if its bytes no
+  /// longer hash to `link`.
+  fn get(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError>;
+
+  fn has(&self, link: &ZLink) -> Result<bool, ZDagError>;
+
+  /// Delete the block stored under `link`, returning whether there was one.
+  fn remove(&mut self, link: &ZLink) -> Result<bool, ZDagError>;
+
+  /// The links of every stored block, in order.
+  fn list(&self) -> Result<Vec<ZLink>, ZDagError>;
+
+  /// Start recording the blocks put through any handle on the store, so that
+  /// `sweep` can spare them, until `end_sweep`. Only one collection may run
+  /// at a time. The default does nothing, for stores no other handle can
+  /// write to while this one is borrowed.
+  fn begin_sweep(&mut self) -> Result<(), ZDagError> {
+    Ok(())
+  }
+
+  /// Delete the block stored under `link` unless it was put since
+  /// `begin_sweep`, returning whether it was deleted.
+  fn sweep(&mut self, link: &ZLink) -> Result<bool, ZDagError> {
+    self.remove(link)
+  }
+
+  /// Stop recording puts.
+  fn end_sweep(&mut self) -> Result<(), ZDagError> {
+    Ok(())
+  }
+
+  /// Store the serialization of `x`, failing with `Invalid` where
+  /// `ZLink::of` would.
+  fn put_expr(&mut self, x: &ZExpr) -> Result<ZLink, ZDagError> {
+    x.check_lengths()?;
+    self.put(&x.serialize())
+  }
+}
+
+fn verify(link: &ZLink, bytes: Vec<u8>) -> Result<Vec<u8>, ZDagError> {
+  if link.verify(&bytes) {
+    Ok(bytes)
+  } else {
+    Err(ZDagError::Corrupt(*link))
+  }
+}
+
+/// Blocks held in memory.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct MemoryStore {
+  blocks: BTreeMap<ZLink, Vec<u8>>,
+}
+
+impl MemoryStore {
+  pub fn new() -> Self {
+    MemoryStore::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.blocks.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.blocks.is_empty()
+  }
+}
+
+impl BlockSource for MemoryStore {
+  fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    self.get(link)
+  }
+}
+
+impl BlockStore for MemoryStore {
+  fn put_with(
+    &mut self,
+    alg: HashAlg,
+    bytes: &[u8],
+  ) -> Result<ZLink, ZDagError> {
+    let link = ZLink::of_bytes_with(alg, bytes);
+    self.blocks.insert(link, bytes.to_vec());
+    Ok(link)
+  }
+
+  fn get(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    match self.blocks.get(link) {
+      Some(bytes) => verify(link, bytes.clone()).map(Some),
+      None => Ok(None),
+    }
+  }
+
+  fn has(&self, link: &ZLink) -> Result<bool, ZDagError> {
+    Ok(self.blocks.contains_key(link))
+  }
+
+  fn remove(&mut self, link: &ZLink) -> Result<bool, ZDagError> {
+    Ok(self.blocks.remove(link).is_some())
+  }
+
+  fn list(&self) -> Result<Vec<ZLink>, ZDagError> {
+    Ok(self.blocks.keys().copied().collect())
+  }
+}
+
+/// Blocks kept as files under a directory. A block is named by the lowercase
+/// hex of its link, and sharded into subdirectories by the first byte, e.g.
+/// `<root>/af/1349b9f5...`, with blocks hashed by another `HashAlg` under a
+/// directory named for it, e.g. `<root>/sha256/af/1349b9f5...`. Blocks are
+/// written to a temporary file and then renamed into place, so a block file
+/// is either whole or absent.
+///
+/// While a collection runs, every put first leaves an empty file named for its
+/// block in `<root>/.gc`, and `sweep` keeps the blocks named there, whichever
+/// handle on the directory put them.
+#[derive(Clone, Debug)]
+pub struct FsStore {
+  root: PathBuf,
+}
+
+/// Distinguishes the temporary files of concurrent writers in one process.
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+impl FsStore {
+  /// A store in the directory `root`, which is created if it doesn't exist.
+  pub fn open<P: AsRef<Path>>(root: P) -> io::Result<Self> {
+    fs::create_dir_all(&root)?;
+    Ok(FsStore {
+      root: root.as_ref().to_path_buf(),
+    })
+  }
+
+  pub fn root(&self) -> &Path {
+    &self.root
+  }
+
+  /// The file a block is stored in.
+  pub fn path(&self, link: &ZLink) -> PathBuf {
+    let hex = to_hex(link.as_bytes());
+    self.alg_dir(link.alg()).join(&hex[..2]).join(&hex[2..])
+  }
+
+  /// Where puts are recorded while a collection runs.
+  fn sweep_dir(&self) -> PathBuf {
+    self.root.join(".gc")
+  }
+
+  fn pin_path(&self, link: &ZLink) -> PathBuf {
+    let name = format!("{}-{}", link.alg().name(), to_hex(link.as_bytes()));
+    self.sweep_dir().join(name)
+  }
+
+  /// A fresh temporary file name in `dir`.
+  fn tmp_path(dir: &Path) -> PathBuf {
+    dir.join(format!(
+      ".tmp-{}-{}",
+      std::process::id(),
+      TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ))
+  }
+
+  fn alg_dir(&self, alg: HashAlg) -> PathBuf {
+    match alg {
+      HashAlg::Blake3 => self.root.clone(),
+      alg => self.root.join(alg.name()),
+    }
+  }
+
+  /// Add the blocks hashed with `alg` to `links`.
+  fn list_alg(
+    &self,
+    alg: HashAlg,
+    links: &mut Vec<ZLink>,
+  ) -> Result<(), ZDagError> {
+    let shards = match fs::read_dir(self.alg_dir(alg)) {
+      Ok(shards) => shards,
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+      Err(e) => return Err(e.into()),
+    };
+    for shard in shards {
+      let shard = shard?;
+      let prefix = shard.file_name();
+      let prefix = match prefix.to_str() {
+        Some(p) if p.len() == 2 && shard.file_type()?.is_dir() => p.to_owned(),
+        _ => continue,
+      };
+      for file in fs::read_dir(shard.path())? {
+        let name = file?.file_name();
+        // skips temporary files and anything else which isn't a block
+        let link = name
+          .to_str()
+          .and_then(|rest| from_hex(&format!("{}{}", prefix, rest)))
+          .and_then(|bytes| ZLink::from_bytes_with(alg, &bytes));
+        if let Some(link) = link {
+          links.push(link);
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+impl BlockSource for FsStore {
+  fn get_block(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    self.get(link)
+  }
+}
+
+impl BlockStore for FsStore {
+  fn put_with(
+    &mut self,
+    alg: HashAlg,
+    bytes: &[u8],
+  ) -> Result<ZLink, ZDagError> {
+    let link = ZLink::of_bytes_with(alg, bytes);
+    let path = self.path(&link);
+    // pinned before looking at the block, so a sweep either sees the pin or
+    // deletes the block before this put checks for it
+    match fs::File::create(self.pin_path(&link)) {
+      Ok(_) => {}
+      // no collection is running
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+      Err(e) => return Err(e.into()),
+    }
+    // a block already on disk is kept if it's intact, and replaced if not
+    match fs::read(&path) {
+      Ok(existing) if link.verify(&existing) => return Ok(link),
+      Ok(_) => {}
+      Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+      Err(e) => return Err(e.into()),
+    }
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let tmp = FsStore::tmp_path(dir);
+    let written = fs::File::create(&tmp).and_then(|mut f| {
+      f.write_all(bytes)?;
+      f.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&tmp, &path)) {
+      Ok(()) => Ok(link),
+      Err(e) => {
+        let _ = fs::remove_file(&tmp);
+        Err(e.into())
+      }
+    }
+  }
+
+  fn get(&self, link: &ZLink) -> Result<Option<Vec<u8>>, ZDagError> {
+    match fs::read(self.path(link)) {
+      Ok(bytes) => verify(link, bytes).map(Some),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  fn has(&self, link: &ZLink) -> Result<bool, ZDagError> {
+    Ok(self.path(link).is_file())
+  }
+
+  fn remove(&mut self, link: &ZLink) -> Result<bool, ZDagError> {
+    match fs::remove_file(self.path(link)) {
+      Ok(()) => Ok(true),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+      Err(e) => Err(e.into()),
+    }
+  }
+
+  fn list(&self) -> Result<Vec<ZLink>, ZDagError> {
+    let mut links = vec![];
+    for alg in &[HashAlg::Blake3, HashAlg::Sha256, HashAlg::Sha512] {
+      self.list_alg(*alg, &mut links)?;
+    }
+    links.sort();
+    Ok(links)
+  }
+
+  fn begin_sweep(&mut self) -> Result<(), ZDagError> {
+    // pins left by a collection which didn't finish
+    self.end_sweep()?;
+    fs::create_dir(self.sweep_dir())?;
+    Ok(())
+  }
+
+  fn sweep(&mut self, link: &ZLink) -> Result<bool, ZDagError> {
+    let path = self.path(link);
+    // moved aside first, so a put which pins the block after the check below
+    // finds it gone and writes it again
+    let tmp = FsStore::tmp_path(path.parent().unwrap());
+    match fs::rename(&path, &tmp) {
+      Ok(()) => {}
+      Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+      Err(e) => return Err(e.into()),
+    }
+    let pinned = match fs::metadata(self.pin_path(link)) {
+      Ok(_) => Ok(true),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+      Err(e) => Err(e),
+    };
+    match pinned {
+      Ok(false) => {
+        fs::remove_file(&tmp)?;
+        Ok(true)
+      }
+      // a put racing this one may have written the block again, with the
+      // same bytes
+      Ok(true) => {
+        fs::rename(&tmp, &path)?;
+        Ok(false)
+      }
+      Err(e) => {
+        let _ = fs::rename(&tmp, &path);
+        Err(e.into())
+      }
+    }
+  }
+
+  fn end_sweep(&mut self) -> Result<(), ZDagError> {
+    match fs::remove_dir_all(self.sweep_dir()) {
+      Ok(()) => Ok(()),
+      Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+      Err(e) => Err(e.into()),
+    }
+  }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+  bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+  if s.len() % 2 != 0
+    || !s.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+  {
+    return None;
+  }
+  (0..s.len())
+    .step_by(2)
+    .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zdag;
+  use crate::ztype::ZType::*;
+
+  fn exercise<S: BlockStore>(store: &mut S) {
+    let x = ZExpr::Cons(vec![ZExpr::Atom(Text(None), b"block".to_vec())]);
+    let y = ZExpr::Atom(Nat(None), vec![1]);
+    let a = store.put_expr(&x).unwrap();
+    let b = store.put_expr(&y).unwrap();
+    assert_eq!(a, ZLink::of(&x).unwrap());
+    assert_eq!(store.put_expr(&x).unwrap(), a);
+    assert!(store.has(&a).unwrap());
+    assert_eq!(store.get(&a).unwrap(), Some(x.serialize()));
+    let mut links = vec![a, b];
+    links.sort();
+    assert_eq!(store.list().unwrap(), links);
+    let missing = ZLink::of(&ZExpr::Cons(vec![])).unwrap();
+    assert!(!store.has(&missing).unwrap());
+    assert_eq!(store.get(&missing).unwrap(), None);
+    assert!(!store.remove(&missing).unwrap());
+    // stores are block sources for the resolvers
+    assert_eq!(zdag::load(store, &a).unwrap(), x);
+    let c = store.put_expr(&ZExpr::Cons(vec![])).unwrap();
+    assert!(store.remove(&c).unwrap());
+    assert!(!store.has(&c).unwrap());
+    assert_eq!(store.list().unwrap(), links);
+    match store.put_expr(&ZExpr::Atom(Nat(Some(2)), vec![1])) {
+      Err(ZDagError::Invalid(e)) => assert_eq!(e.path, Vec::<usize>::new()),
+      y => panic!("unexpected {:?}", y),
+    }
+    assert_eq!(store.list().unwrap(), links);
+    // blocks hashed another way are kept apart from their blake3 twins
+    let bytes = x.serialize();
+    let s = store.put_with(HashAlg::Sha256, &bytes).unwrap();
+    assert_eq!(s, ZLink::of_with(HashAlg::Sha256, &x).unwrap());
+    assert_ne!(s, a);
+    assert_eq!(store.get(&s).unwrap(), Some(bytes));
+    assert!(store.list().unwrap().contains(&s));
+    assert!(store.remove(&s).unwrap());
+    assert!(store.has(&a).unwrap());
+  }
+
+  #[test]
+  fn zstore_memory() {
+    let mut store = MemoryStore::new();
+    exercise(&mut store);
+    assert_eq!(store.len(), 2);
+    let link = *store.blocks.keys().next().unwrap();
+    store.blocks.get_mut(&link).unwrap().push(0);
+    match store.get(&link) {
+      Err(ZDagError::Corrupt(l)) => assert_eq!(l, link),
+      y => panic!("unexpected {:?}", y),
+    }
+    let original =
+      store.blocks[&link][..store.blocks[&link].len() - 1].to_vec();
+    assert_eq!(store.put(&original).unwrap(), link);
+    assert_eq!(store.get(&link).unwrap(), Some(original));
+  }
+
+  #[test]
+  fn zstore_fs() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut store = FsStore::open(dir.path().join("blocks")).unwrap();
+    exercise(&mut store);
+    let link = store.list().unwrap()[0];
+    let path = store.path(&link);
+    let hex = to_hex(link.as_bytes());
+    assert!(path.ends_with(format!("{}/{}", &hex[..2], &hex[2..])));
+    // leftover temporary files aren't blocks
+    fs::write(path.parent().unwrap().join(".tmp-1-1"), b"partial").unwrap();
+    assert_eq!(store.list().unwrap().len(), 2);
+    // a reopened store sees the same blocks
+    let reopened = FsStore::open(store.root()).unwrap();
+    assert_eq!(reopened.list().unwrap(), store.list().unwrap());
+    let original = fs::read(&path).unwrap();
+    fs::write(&path, b"tampered").unwrap();
+    match store.get(&link) {
+      Err(ZDagError::Corrupt(l)) => assert_eq!(l, link),
+      y => panic!("unexpected {:?}", y),
+    }
+    // putting the block again repairs it
+    assert_eq!(store.put(&original).unwrap(), link);
+    assert_eq!(store.get(&link).unwrap(), Some(original));
+  }
+
+  #[test]
+  fn zstore_hex() {
+    let bytes = [0x00, 0x0f, 0xa0, 0xff];
+    assert_eq!(to_hex(&bytes), "000fa0ff");
+    assert_eq!(from_hex("000fa0ff"), Some(bytes.to_vec()));
+    assert_eq!(from_hex("000FA0FF"), None);
+    assert_eq!(from_hex("abc"), None);
+  }
+}
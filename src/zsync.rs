@@ -0,0 +1,520 @@
+//! Copying the blocks under a root link from one `BlockStore` to another,
+//! sending only the blocks the receiving store lacks.
+//!
+//! Peers exchange four messages, each a `ZExpr`:
+//!
+//! ```text
+//! (have:symbol <link> ...)   offers roots, or answers a want
+//! (want:symbol <link> ...)   asks for blocks
+//! (block:symbol <bytes>)     one requested block
+//! (done:symbol)              ends the session
+//! ```
+//!
+//! The side fetching blocks (`pull`, or `receive` for a push) walks the tree
+//! under the root through its own store, and sends a `want` for every link it
+//! can't find there. The side holding the blocks (`serve`, or `push`) answers
+//! each `want` with a `have` listing those of the links it holds, followed by
+//! a `block` for each of them in the same order. Every block received is
+//! checked against its link before it is stored, and the links inside it are
+//! wanted in turn, until nothing under the root is missing.
+
+use core::fmt;
+use std::collections::BTreeSet;
+use std::io;
+use std::io::{Read, Write};
+use std::sync::mpsc;
+
+use crate::limits::DecodeLimits;
+use crate::zdag;
+use crate::zdag::ZDagError;
+use crate::zlink::ZLink;
+use crate::zreader::{ZExprReader, ZReadError};
+use crate::zserde::ser::symbol;
+use crate::zstore::BlockStore;
+use crate::ztype::ZType;
+use crate::ZExpr;
+
+#[derive(Debug)]
+pub enum SyncError {
+  Store(ZDagError),
+  Read(ZReadError),
+  Io(io::Error),
+  /// The peer hung up before the session was done
+  Closed,
+  /// The peer sent a message that doesn't fit the protocol here: (message)
+  Unexpected(String),
+  /// The peer doesn't have a block it was asked for
+  PeerMissing(ZLink),
+}
+
+impl fmt::Display for SyncError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Store(e) => write!(f, "{}", e),
+      Self::Read(e) => write!(f, "{}", e),
+      Self::Io(e) => write!(f, "{}", e),
+      Self::Closed => write!(f, "peer closed the connection"),
+      Self::Unexpected(msg) => write!(f, "unexpected message {}", msg),
+      Self::PeerMissing(link) => write!(f, "peer doesn't have block {}", link),
+    }
+  }
+}
+
+impl std::error::Error for SyncError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      Self::Store(e) => Some(e),
+      Self::Read(e) => Some(e),
+      Self::Io(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<ZDagError> for SyncError {
+  fn from(e: ZDagError) -> Self {
+    SyncError::Store(e)
+  }
+}
+
+impl From<ZReadError> for SyncError {
+  fn from(e: ZReadError) -> Self {
+    SyncError::Read(e)
+  }
+}
+
+impl From<io::Error> for SyncError {
+  fn from(e: io::Error) -> Self {
+    SyncError::Io(e)
+  }
+}
+
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum SyncMsg {
+  Have(Vec<ZLink>),
+  Want(Vec<ZLink>),
+  Block(Vec<u8>),
+  Done,
+}
+
+impl SyncMsg {
+  pub fn to_zexpr(&self) -> ZExpr {
+    let links = |name, ls: &[ZLink]| {
+      let mut xs = vec![symbol(name)];
+      xs.extend(ls.iter().map(ZLink::to_zexpr));
+      ZExpr::Cons(xs)
+    };
+    match self {
+      Self::Have(ls) => links("have", ls),
+      Self::Want(ls) => links("want", ls),
+      Self::Block(bytes) => ZExpr::Cons(vec![
+        symbol("block"),
+        ZExpr::Atom(ZType::Bytes(None), bytes.clone()),
+      ]),
+      Self::Done => ZExpr::Cons(vec![symbol("done")]),
+    }
+  }
+
+  pub fn from_zexpr(x: &ZExpr) -> Option<Self> {
+    let (name, args) = match x {
+      ZExpr::Cons(xs) => match xs.split_first() {
+        Some((ZExpr::Atom(ZType::Symbol(None), name), args)) => (name, args),
+        _ => return None,
+      },
+      _ => return None,
+    };
+    let links = || args.iter().map(ZLink::from_zexpr).collect::<Option<_>>();
+    match (name.as_slice(), args) {
+      (b"have", _) => links().map(Self::Have),
+      (b"want", _) => links().map(Self::Want),
+      (b"block", [ZExpr::Atom(ZType::Bytes(None), bytes)]) => {
+        Some(Self::Block(bytes.clone()))
+      }
+      (b"done", []) => Some(Self::Done),
+      _ => None,
+    }
+  }
+}
+
+/// A connection to a peer carrying whole `ZExpr` messages in order.
+pub trait Transport {
+  fn send(&mut self, msg: &ZExpr) -> Result<(), SyncError>;
+
+  /// The next message, failing with `Closed` if the peer has hung up.
+  fn recv(&mut self) -> Result<ZExpr, SyncError>;
+}
+
+/// One end of an in-process connection.
+pub struct ChannelTransport {
+  tx: mpsc::Sender<ZExpr>,
+  rx: mpsc::Receiver<ZExpr>,
+}
+
+impl ChannelTransport {
+  /// Both ends of a new connection.
+  pub fn pair() -> (Self, Self) {
+    let (a_tx, b_rx) = mpsc::channel();
+    let (b_tx, a_rx) = mpsc::channel();
+    (
+      ChannelTransport { tx: a_tx, rx: a_rx },
+      ChannelTransport { tx: b_tx, rx: b_rx },
+    )
+  }
+}
+
+impl Transport for ChannelTransport {
+  fn send(&mut self, msg: &ZExpr) -> Result<(), SyncError> {
+    self.tx.send(msg.clone()).map_err(|_| SyncError::Closed)
+  }
+
+  fn recv(&mut self) -> Result<ZExpr, SyncError> {
+    self.rx.recv().map_err(|_| SyncError::Closed)
+  }
+}
+
+/// Messages serialized back to back over a byte stream, such as a socket.
+/// Incoming messages are decoded within `DecodeLimits::default()`.
+pub struct StreamTransport<R, W> {
+  reader: ZExprReader<R>,
+  writer: W,
+}
+
+impl<R: Read, W: Write> StreamTransport<R, W> {
+  /// A transport reading from `reader` and writing to `writer`, usually the
+  /// two halves of one stream, e.g. a `UnixStream` and its `try_clone`.
+  pub fn new(reader: R, writer: W) -> Self {
+    StreamTransport {
+      reader: ZExprReader::with_limits(reader, DecodeLimits::default()),
+      writer,
+    }
+  }
+}
+
+impl<R: Read, W: Write> Transport for StreamTransport<R, W> {
+  fn send(&mut self, msg: &ZExpr) -> Result<(), SyncError> {
+    msg.serialize_into(&mut self.writer)?;
+    self.writer.flush()?;
+    Ok(())
+  }
+
+  fn recv(&mut self) -> Result<ZExpr, SyncError> {
+    self.reader.read_expr()?.ok_or(SyncError::Closed)
+  }
+}
+
+/// Counts of the blocks a session moved.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct SyncStats {
+  pub blocks: usize,
+  pub bytes: u64,
+}
+
+fn send<T: Transport + ?Sized>(
+  t: &mut T,
+  msg: SyncMsg,
+) -> Result<(), SyncError> {
+  t.send(&msg.to_zexpr())
+}
+
+fn recv<T: Transport + ?Sized>(t: &mut T) -> Result<SyncMsg, SyncError> {
+  let x = t.recv()?;
+  SyncMsg::from_zexpr(&x).ok_or_else(|| SyncError::Unexpected(format!("{}", x)))
+}
+
+fn unexpected(msg: SyncMsg) -> SyncError {
+  SyncError::Unexpected(format!("{}", msg.to_zexpr()))
+}
+
+/// The links inside a block.
+fn links_in(block: &ZExpr) -> Vec<ZLink> {
+  let mut links = vec![];
+  let mut stack = vec![block];
+  while let Some(x) = stack.pop() {
+    match x {
+      ZExpr::Cons(xs) => stack.extend(xs.iter()),
+      atom => links.extend(ZLink::from_zexpr(atom)),
+    }
+  }
+  links
+}
+
+/// Add to `missing` every link under `links` which `store` lacks, walking
+/// through the blocks it has.
+fn find_missing<S: BlockStore + ?Sized>(
+  store: &S,
+  links: Vec<ZLink>,
+  seen: &mut BTreeSet<ZLink>,
+  missing: &mut Vec<ZLink>,
+) -> Result<(), SyncError> {
+  let mut todo = links;
+  while let Some(link) = todo.pop() {
+    if !seen.insert(link) {
+      continue;
+    }
+    if store.has(&link)? {
+      todo.extend(links_in(&zdag::load(store, &link)?));
+    } else {
+      missing.push(link);
+    }
+  }
+  Ok(())
+}
+
+/// Fetch from the peer every block under `roots` which `store` lacks. Ends
+/// the session with `done` once nothing is missing.
+pub fn pull<T: Transport + ?Sized, S: BlockStore + ?Sized>(
+  t: &mut T,
+  store: &mut S,
+  roots: &[ZLink],
+) -> Result<SyncStats, SyncError> {
+  let mut stats = SyncStats::default();
+  let mut seen = BTreeSet::new();
+  let mut wanted = vec![];
+  find_missing(store, roots.to_vec(), &mut seen, &mut wanted)?;
+  while !wanted.is_empty() {
+    send(t, SyncMsg::Want(wanted.clone()))?;
+    let have = match recv(t)? {
+      SyncMsg::Have(have) => have,
+      msg => return Err(unexpected(msg)),
+    };
+    if let Some(link) = wanted.iter().find(|l| !have.contains(l)) {
+      return Err(SyncError::PeerMissing(*link));
+    }
+    if have != wanted {
+      return Err(unexpected(SyncMsg::Have(have)));
+    }
+    let mut next = vec![];
+    for link in have {
+      let bytes = match recv(t)? {
+        SyncMsg::Block(bytes) => bytes,
+        msg => return Err(unexpected(msg)),
+      };
+      // nothing is stored unless it is a well-formed block
+      let block = zdag::decode_block(&link, &bytes)?;
+      store.put_with(link.alg(), &bytes)?;
+      stats.blocks += 1;
+      stats.bytes += bytes.len() as u64;
+      find_missing(store, links_in(&block), &mut seen, &mut next)?;
+    }
+    wanted = next;
+  }
+  send(t, SyncMsg::Done)?;
+  Ok(stats)
+}
+
+/// Answer the peer's wants from `store` until it sends `done`.
+pub fn serve<T: Transport + ?Sized, S: BlockStore + ?Sized>(
+  t: &mut T,
+  store: &S,
+) -> Result<SyncStats, SyncError> {
+  let mut stats = SyncStats::default();
+  loop {
+    match recv(t)? {
+      SyncMsg::Want(links) => {
+        let mut blocks = vec![];
+        for link in links {
+          if let Some(bytes) = store.get(&link)? {
+            blocks.push((link, bytes));
+          }
+        }
+        send(t, SyncMsg::Have(blocks.iter().map(|(l, _)| *l).collect()))?;
+        for (_, bytes) in blocks {
+          stats.blocks += 1;
+          stats.bytes += bytes.len() as u64;
+          send(t, SyncMsg::Block(bytes))?;
+        }
+      }
+      SyncMsg::Done => return Ok(stats),
+      msg => return Err(unexpected(msg)),
+    }
+  }
+}
+
+/// Offer `roots` to the peer and send it whatever blocks under them it
+/// wants. The peer should be running `receive`.
+pub fn push<T: Transport + ?Sized, S: BlockStore + ?Sized>(
+  t: &mut T,
+  store: &S,
+  roots: &[ZLink],
+) -> Result<SyncStats, SyncError> {
+  send(t, SyncMsg::Have(roots.to_vec()))?;
+  serve(t, store)
+}
+
+/// Accept the roots a peer running `push` offers, pulling every block under
+/// them which `store` lacks. Returns the roots along with the counts.
+pub fn receive<T: Transport + ?Sized, S: BlockStore + ?Sized>(
+  t: &mut T,
+  store: &mut S,
+) -> Result<(Vec<ZLink>, SyncStats), SyncError> {
+  let roots = match recv(t)? {
+    SyncMsg::Have(roots) => roots,
+    msg => return Err(unexpected(msg)),
+  };
+  let stats = pull(t, store, &roots)?;
+  Ok((roots, stats))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::zlink::HashAlg;
+  use crate::zstore::MemoryStore;
+  use crate::ztype::ZType::*;
+  use std::thread;
+
+  fn text(s: &str) -> ZExpr {
+    ZExpr::Atom(Text(None), s.as_bytes().to_vec())
+  }
+
+  fn doc(n: usize) -> ZExpr {
+    let shared = ZExpr::Cons(vec![text("shared"); 3]);
+    ZExpr::Cons(
+      (0..n)
+        .map(|i| ZExpr::Cons(vec![text(&i.to_string()), shared.clone()]))
+        .collect(),
+    )
+  }
+
+  fn put_tree(store: &mut MemoryStore, x: &ZExpr) -> ZLink {
+    let (root, blocks) = zdag::split(x, 0).unwrap();
+    for bytes in blocks.values() {
+      store.put(bytes).unwrap();
+    }
+    ZLink::from_zexpr(&root).unwrap()
+  }
+
+  #[test]
+  fn zsync_msg_roundtrip() {
+    let link = ZLink::of(&text("x")).unwrap();
+    let msgs = vec![
+      SyncMsg::Have(vec![link]),
+      SyncMsg::Want(vec![link, link]),
+      SyncMsg::Want(vec![]),
+      SyncMsg::Block(vec![1, 2, 3]),
+      SyncMsg::Done,
+    ];
+    for msg in msgs {
+      assert_eq!(SyncMsg::from_zexpr(&msg.to_zexpr()), Some(msg));
+    }
+    let bad = ZExpr::Cons(vec![symbol("done"), text("x")]);
+    assert_eq!(SyncMsg::from_zexpr(&bad), None);
+    let bad = ZExpr::Cons(vec![symbol("want"), text("x")]);
+    assert_eq!(SyncMsg::from_zexpr(&bad), None);
+  }
+
+  #[test]
+  fn zsync_pull_over_channels() {
+    let mut server = MemoryStore::new();
+    let root = put_tree(&mut server, &doc(4));
+    let (mut a, mut b) = ChannelTransport::pair();
+    let handle =
+      thread::spawn(move || serve(&mut b, &server).map(|s| (s, server)));
+    let mut client = MemoryStore::new();
+    let stats = pull(&mut a, &mut client, &[root]).unwrap();
+    let (served, server) = handle.join().unwrap().unwrap();
+    assert_eq!(stats, served);
+    // the shared subtree is one block, sent once
+    assert_eq!(stats.blocks, 6);
+    assert_eq!(client, server);
+    assert_eq!(zdag::resolve(&client, &root.into()).unwrap(), doc(4));
+    // pulling again sends nothing
+    let (mut a, mut b) = ChannelTransport::pair();
+    let handle = thread::spawn(move || serve(&mut b, &server));
+    assert_eq!(pull(&mut a, &mut client, &[root]).unwrap().blocks, 0);
+    assert_eq!(handle.join().unwrap().unwrap().blocks, 0);
+  }
+
+  #[test]
+  fn zsync_pull_sha256_root() {
+    let mut server = MemoryStore::new();
+    let child = put_tree(&mut server, &doc(2));
+    let x = ZExpr::Cons(vec![text("sha"), child.into()]);
+    let root = server.put_with(HashAlg::Sha256, &x.serialize()).unwrap();
+    let (mut a, mut b) = ChannelTransport::pair();
+    let handle = thread::spawn(move || serve(&mut b, &server));
+    let mut client = MemoryStore::new();
+    let stats = pull(&mut a, &mut client, &[root]).unwrap();
+    assert_eq!(handle.join().unwrap().unwrap(), stats);
+    // the root is filed under its own link, not its blake3 twin
+    assert!(client.has(&root).unwrap());
+    assert_eq!(client.list().unwrap().len(), stats.blocks as usize);
+    let y = ZExpr::Cons(vec![text("sha"), doc(2)]);
+    assert_eq!(zdag::resolve(&client, &root.into()).unwrap(), y);
+  }
+
+  #[test]
+  fn zsync_sends_only_missing() {
+    let mut server = MemoryStore::new();
+    let old = put_tree(&mut server, &doc(2));
+    let new = put_tree(&mut server, &doc(3));
+    let mut client = MemoryStore::new();
+    put_tree(&mut client, &doc(2));
+    let (mut a, mut b) = ChannelTransport::pair();
+    let handle = thread::spawn(move || {
+      let mut client = client;
+      receive(&mut b, &mut client).map(|r| (r, client))
+    });
+    let pushed = push(&mut a, &server, &[new]).unwrap();
+    let ((roots, stats), client) = handle.join().unwrap().unwrap();
+    assert_eq!(roots, vec![new]);
+    assert_eq!(stats, pushed);
+    // only the new root and the new element are sent
+    assert_eq!(stats.blocks, 2);
+    assert!(client.has(&old).unwrap());
+    assert_eq!(zdag::resolve(&client, &new.into()).unwrap(), doc(3));
+  }
+
+  #[test]
+  fn zsync_peer_missing() {
+    let (mut a, mut b) = ChannelTransport::pair();
+    let handle = thread::spawn(move || serve(&mut b, &MemoryStore::new()));
+    let root = ZLink::of(&text("nowhere")).unwrap();
+    match pull(&mut a, &mut MemoryStore::new(), &[root]) {
+      Err(SyncError::PeerMissing(l)) => assert_eq!(l, root),
+      y => panic!("unexpected {:?}", y),
+    }
+    drop(a);
+    match handle.join().unwrap() {
+      Err(SyncError::Closed) => {}
+      y => panic!("unexpected {:?}", y),
+    }
+  }
+
+  #[test]
+  fn zsync_rejects_bad_blocks() {
+    let root = ZLink::of(&doc(1)).unwrap();
+    let (mut a, mut b) = ChannelTransport::pair();
+    let handle = thread::spawn(move || {
+      recv(&mut b)?;
+      send(&mut b, SyncMsg::Have(vec![root]))?;
+      send(&mut b, SyncMsg::Block(doc(2).serialize()))
+    });
+    let mut client = MemoryStore::new();
+    match pull(&mut a, &mut client, &[root]) {
+      Err(SyncError::Store(ZDagError::Corrupt(l))) => assert_eq!(l, root),
+      y => panic!("unexpected {:?}", y),
+    }
+    handle.join().unwrap().unwrap();
+    assert!(client.is_empty());
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn zsync_pull_over_unix_socket() {
+    use std::os::unix::net::UnixStream;
+
+    let mut server = MemoryStore::new();
+    let root = put_tree(&mut server, &doc(3));
+    let (s, c) = UnixStream::pair().unwrap();
+    let handle = thread::spawn(move || {
+      let mut t = StreamTransport::new(s.try_clone().unwrap(), s);
+      serve(&mut t, &server)
+    });
+    let mut t = StreamTransport::new(c.try_clone().unwrap(), c);
+    let mut client = MemoryStore::new();
+    let stats = pull(&mut t, &mut client, &[root]).unwrap();
+    assert_eq!(handle.join().unwrap().unwrap(), stats);
+    assert_eq!(zdag::resolve(&client, &root.into()).unwrap(), doc(3));
+  }
+}
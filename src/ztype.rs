@@ -1,3 +1,4 @@
+use std::collections::BTreeMap;
 use std::fmt;
 
 use nom::error::ErrorKind;
@@ -6,21 +7,100 @@ use nom::error::ParseError;
 use nom::sequence::preceded;
 use nom::InputLength;
 use nom::{
-  branch::alt, bytes::complete::tag, character::complete::digit0,
-  combinator::map, IResult,
+  bytes::complete::tag,
+  character::complete::{digit0, hex_digit1},
+  IResult,
 };
 use std::num::ParseIntError;
 
-#[derive(PartialEq, Eq, Debug, Clone, Copy)]
-pub enum ZType {
-  Bytes(Option<u64>),
-  Symbol(Option<u64>),
-  Nat(Option<u64>),
-  Int(Option<u64>),
-  Float(Option<u64>),
-  Text(Option<u64>),
-  Char(Option<u64>),
-  Hash(Option<u64>),
+use crate::zvalid::{self, Violation};
+
+/// Defines `ZType` and its code and name maps from the rows of `ztype.csv`,
+/// which `build.rs` turns into an invocation of this macro.
+macro_rules! ztypes {
+  ($(($code:literal, $variant:ident, $name:literal, $doc:literal)),* $(,)?) => {
+    #[derive(PartialEq, Eq, Debug, Clone, Copy)]
+    pub enum ZType {
+      $(#[doc = $doc] $variant(Option<u64>),)*
+      /// A type outside the built-in table: (code, length). It is given a
+      /// name and validator by a `ZTypeRegistry`.
+      Ext(ExtCode, Option<u64>),
+    }
+
+    /// The name, code and constructor of each built-in type, in `ztype.csv`
+    /// order.
+    pub const BUILTINS: &[(&str, u8, fn(Option<u64>) -> ZType)] =
+      &[$(($name, $code, ZType::$variant)),*];
+
+    impl ZType {
+      /// The names of the variants, in order.
+      pub(crate) const VARIANTS: &'static [&'static str] =
+        &[$(stringify!($variant),)* "Ext"];
+
+      /// The name of the type in the text syntax, or `ext` for extension
+      /// types.
+      pub fn name(&self) -> &'static str {
+        match self {
+          $(Self::$variant(_) => $name,)*
+          Self::Ext(..) => "ext",
+        }
+      }
+      /// The type code as a big-endian number.
+      pub fn code(&self) -> u64 {
+        match self {
+          $(Self::$variant(_) => $code,)*
+          Self::Ext(code, _) => code.get(),
+        }
+      }
+      /// The serialized type code.
+      pub fn serialize(&self) -> &[u8] {
+        match self {
+          $(Self::$variant(_) => &[$code],)*
+          Self::Ext(code, _) => code.as_bytes(),
+        }
+      }
+      /// The length of the type, if it has one.
+      pub fn length(&self) -> Option<u64> {
+        match *self {
+          $(Self::$variant(len))|* | Self::Ext(_, len) => len,
+        }
+      }
+    }
+  };
+}
+
+include!(concat!(env!("OUT_DIR"), "/ztypes.rs"));
+
+/// The code of an extension type. It takes 2 to 8 bytes, since the 1 byte
+/// codes are reserved for the built-in types.
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct ExtCode([u8; 8]);
+
+impl ExtCode {
+  /// `None` for the 1 byte codes.
+  pub fn new(code: u64) -> Option<Self> {
+    if code > 0xff {
+      Some(ExtCode(code.to_be_bytes()))
+    } else {
+      None
+    }
+  }
+
+  pub fn get(&self) -> u64 {
+    u64::from_be_bytes(self.0)
+  }
+
+  /// The code's big-endian bytes, without leading zeros.
+  pub fn as_bytes(&self) -> &[u8] {
+    let zeros = self.0.iter().take_while(|b| **b == 0).count();
+    &self.0[zeros..]
+  }
+}
+
+impl fmt::Debug for ExtCode {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "ExtCode({:#x})", self.get())
+  }
 }
 
 impl fmt::Display for ZType {
@@ -31,61 +111,37 @@ impl fmt::Display for ZType {
         None => String::from(""),
       }
     };
-    write!(
-      f,
-      "{}",
-      match self {
-        Self::Bytes(x) => format!("{}{}", "bytes", index_str(*x)),
-        Self::Symbol(x) => format!("{}{}", "symbol", index_str(*x)),
-        Self::Nat(x) => format!("{}{}", "nat", index_str(*x)),
-        Self::Int(x) => format!("{}{}", "int", index_str(*x)),
-        Self::Float(x) => format!("{}{}", "float", index_str(*x)),
-        Self::Text(x) => format!("{}{}", "text", index_str(*x)),
-        Self::Char(x) => format!("{}{}", "char", index_str(*x)),
-        Self::Hash(x) => format!("{}{}", "hash", index_str(*x)),
+    match self {
+      Self::Ext(code, Some(len)) => {
+        write!(f, "ext{:x}_{}", code.get(), len * 8)
       }
-    )
+      Self::Ext(code, None) => write!(f, "ext{:x}", code.get()),
+      _ => write!(f, "{}{}", self.name(), index_str(self.length())),
+    }
   }
 }
 
 impl ZType {
-  pub fn serialize(&self) -> &[u8] {
-    match self {
-      Self::Bytes(_) => &[0x00],
-      Self::Symbol(_) => &[0x01],
-      Self::Nat(_) => &[0x02],
-      Self::Int(_) => &[0x03],
-      Self::Float(_) => &[0x04],
-      Self::Text(_) => &[0x05],
-      Self::Char(_) => &[0x06],
-      Self::Hash(_) => &[0x07],
-    }
+  /// The number of bytes in the serialized type code.
+  pub fn code_len(&self) -> usize {
+    crate::number_of_bytes(self.code()) as usize
   }
   pub fn deserialize(i: &[u8], len: Option<u64>) -> Option<Self> {
-    match i {
-      &[0x00] => Some(Self::Bytes(len)),
-      &[0x01] => Some(Self::Symbol(len)),
-      &[0x02] => Some(Self::Nat(len)),
-      &[0x03] => Some(Self::Int(len)),
-      &[0x04] => Some(Self::Float(len)),
-      &[0x05] => Some(Self::Text(len)),
-      &[0x06] => Some(Self::Char(len)),
-      &[0x07] => Some(Self::Hash(len)),
+    match *i {
+      [code] => BUILTINS
+        .iter()
+        .find(|(_, x, _)| *x == code)
+        .map(|(_, _, typ)| typ(len)),
+      // longer codes with leading zeros would alias shorter ones
+      [first, ..] if first != 0 && i.len() <= 8 => {
+        ExtCode::new(i.iter().fold(0, |acc, &x| (acc << 8) | u64::from(x)))
+          .map(|code| Self::Ext(code, len))
+      }
       _ => None,
     }
   }
   pub fn is_some_len(&self) -> bool {
-    match self {
-      Self::Bytes(Some(_)) => true,
-      Self::Symbol(Some(_)) => true,
-      Self::Nat(Some(_)) => true,
-      Self::Int(Some(_)) => true,
-      Self::Float(Some(_)) => true,
-      Self::Text(Some(_)) => true,
-      Self::Char(Some(_)) => true,
-      Self::Hash(Some(_)) => true,
-      _ => false,
-    }
+    self.length().is_some()
   }
 }
 
@@ -93,6 +149,8 @@ impl ZType {
 pub enum ZTypeError<I> {
   UnalignedTypeIndex(I, u64),
   InvalidU64TypeIndex(I, ParseIntError),
+  /// An `ext` type whose hex code doesn't take 2 to 8 bytes
+  InvalidExtCode(I),
   NomErr(I, ErrorKind),
 }
 
@@ -101,6 +159,7 @@ impl<I> ZTypeError<I> {
     match self {
       Self::UnalignedTypeIndex(i, _) => i,
       Self::InvalidU64TypeIndex(i, _) => i,
+      Self::InvalidExtCode(i) => i,
       Self::NomErr(i, _) => i,
     }
   }
@@ -140,7 +199,7 @@ impl<I> FromExternalError<I, ZTypeError<I>> for ZTypeError<I> {
 
 pub fn parse_index(i: &str) -> IResult<&str, Option<u64>, ZTypeError<&str>> {
   let (i, o) = digit0(i)?;
-  if o.len() == 0 {
+  if o.is_empty() {
     Ok((i, None))
   } else {
     match o.parse::<u64>() {
@@ -157,38 +216,191 @@ pub fn parse_index(i: &str) -> IResult<&str, Option<u64>, ZTypeError<&str>> {
 }
 
 pub fn parse(input: &str) -> IResult<&str, ZType, ZTypeError<&str>> {
-  alt((
-    map(preceded(tag("bytes"), parse_index), ZType::Bytes),
-    map(preceded(tag("symbol"), parse_index), ZType::Symbol),
-    map(preceded(tag("nat"), parse_index), ZType::Nat),
-    map(preceded(tag("int"), parse_index), ZType::Int),
-    map(preceded(tag("float"), parse_index), ZType::Float),
-    map(preceded(tag("text"), parse_index), ZType::Text),
-    map(preceded(tag("char"), parse_index), ZType::Char),
-    map(preceded(tag("hash"), parse_index), ZType::Hash),
-  ))(input)
+  let builtin = BUILTINS
+    .iter()
+    .filter(|(name, ..)| input.starts_with(name))
+    .max_by_key(|(name, ..)| name.len());
+  match builtin {
+    Some((name, _, typ)) => {
+      let (i, len) = parse_index(&input[name.len()..])?;
+      Ok((i, typ(len)))
+    }
+    None => preceded(tag("ext"), parse_ext)(input),
+  }
+}
+
+/// The hex code and optional `_<bits>` index of an unnamed extension type.
+fn parse_ext(i: &str) -> IResult<&str, ZType, ZTypeError<&str>> {
+  let (i, hex) = hex_digit1(i)?;
+  let code = match u64::from_str_radix(hex, 16).ok().and_then(ExtCode::new) {
+    Some(code) => code,
+    None => return Err(nom::Err::Error(ZTypeError::InvalidExtCode(i))),
+  };
+  let (i, len) = match i.strip_prefix('_') {
+    Some(rest) => parse_index(rest)?,
+    None => (i, None),
+  };
+  Ok((i, ZType::Ext(code, len)))
+}
+
+/// Checks the data of an atom of a registered extension type.
+pub type Validator = fn(&[u8]) -> bool;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum ZTypeRegistryError {
+  /// A code which doesn't take 2 to 8 bytes: (code)
+  ReservedCode(u64),
+  CodeTaken(u64),
+  NameTaken(String),
+  /// A name which isn't lowercase letters, digits and dashes beginning and
+  /// ending with a letter, or which a built-in name begins with
+  BadName(String),
+}
+
+impl fmt::Display for ZTypeRegistryError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::ReservedCode(code) => {
+        write!(f, "type code {:#x} is reserved for built-in types", code)
+      }
+      Self::CodeTaken(code) => {
+        write!(f, "type code {:#x} is already registered", code)
+      }
+      Self::NameTaken(name) => {
+        write!(f, "type name {:?} is already registered", name)
+      }
+      Self::BadName(name) => write!(f, "invalid type name {:?}", name),
+    }
+  }
+}
+
+impl std::error::Error for ZTypeRegistryError {}
+
+/// Names and validators for extension types, so that their atoms can be
+/// parsed, printed and checked like those of the built-in types. Without a
+/// registry an extension type is written `ext<hex code>`, and its atoms never
+/// validate.
+#[derive(Clone, Debug, Default)]
+pub struct ZTypeRegistry {
+  types: BTreeMap<ExtCode, (String, Option<Validator>)>,
+  codes: BTreeMap<String, ExtCode>,
+}
+
+impl ZTypeRegistry {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Name the extension type `code`, whose atoms are valid when `validator`
+  /// accepts their data, or always when there's no validator.
+  pub fn register(
+    &mut self,
+    code: u64,
+    name: &str,
+    validator: Option<Validator>,
+  ) -> Result<(), ZTypeRegistryError> {
+    let bytes = name.as_bytes();
+    let valid_name = bytes.first().is_some_and(u8::is_ascii_lowercase)
+      && bytes.last().is_some_and(u8::is_ascii_lowercase)
+      && bytes
+        .iter()
+        .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || *b == b'-')
+      && !name.starts_with("ext")
+      && !BUILTINS
+        .iter()
+        .map(|(x, ..)| *x)
+        .chain(std::iter::once("ext"))
+        .any(|x| x.starts_with(name));
+    let code = match ExtCode::new(code) {
+      Some(code) => code,
+      None => return Err(ZTypeRegistryError::ReservedCode(code)),
+    };
+    if !valid_name {
+      Err(ZTypeRegistryError::BadName(name.to_owned()))
+    } else if self.types.contains_key(&code) {
+      Err(ZTypeRegistryError::CodeTaken(code.get()))
+    } else if self.codes.contains_key(name) {
+      Err(ZTypeRegistryError::NameTaken(name.to_owned()))
+    } else {
+      self.types.insert(code, (name.to_owned(), validator));
+      self.codes.insert(name.to_owned(), code);
+      Ok(())
+    }
+  }
+
+  pub fn name(&self, code: u64) -> Option<&str> {
+    let code = ExtCode::new(code)?;
+    self.types.get(&code).map(|(name, _)| name.as_str())
+  }
+
+  pub fn code(&self, name: &str) -> Option<u64> {
+    self.codes.get(name).map(ExtCode::get)
+  }
+
+  /// Like `ZType`'s `Display`, but with registered extension types named.
+  pub fn display(&self, typ: ZType) -> String {
+    match (typ, self.name(typ.code())) {
+      (ZType::Ext(_, None), Some(name)) => name.to_owned(),
+      (ZType::Ext(_, Some(len)), Some(name)) => format!("{}{}", name, len * 8),
+      _ => typ.to_string(),
+    }
+  }
+
+  /// Like `parse`, but also accepts the names of registered types.
+  pub fn parse<'a>(
+    &self,
+    i: &'a str,
+  ) -> IResult<&'a str, ZType, ZTypeError<&'a str>> {
+    // in reverse order a name comes after every name it begins, so the
+    // longest match wins
+    for (name, code) in self.codes.iter().rev() {
+      if let Some(rest) = i.strip_prefix(name.as_str()) {
+        let (rest, len) = parse_index(rest)?;
+        return Ok((rest, ZType::Ext(*code, len)));
+      }
+    }
+    parse(i)
+  }
+
+  /// Like `zvalid::check_atom`, but extension types are checked with their
+  /// validators instead of being rejected.
+  pub fn check_atom(&self, typ: ZType, dat: &[u8]) -> Result<(), Violation> {
+    let validator = match typ {
+      ZType::Ext(code, _) => self.types.get(&code),
+      _ => None,
+    };
+    match validator {
+      Some((_, validator)) => {
+        zvalid::check_length(typ, dat)?;
+        if validator.map_or(true, |valid| valid(dat)) {
+          Ok(())
+        } else {
+          Err(Violation::Rejected(typ.code()))
+        }
+      }
+      None => zvalid::check_atom(typ, dat),
+    }
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use nom::combinator::map;
   use nom::Err::Error;
   use quickcheck::{Arbitrary, Gen};
   use rand::Rng;
 
+  fn ext(code: u64, len: Option<u64>) -> ZType {
+    ZType::Ext(ExtCode::new(code).unwrap(), len)
+  }
+
   impl Arbitrary for ZType {
     fn arbitrary<G: Gen>(g: &mut G) -> Self {
       let x: u32 = g.gen();
-      match x % 8 {
-        0 => ZType::Bytes(Arbitrary::arbitrary(g)),
-        1 => ZType::Symbol(Arbitrary::arbitrary(g)),
-        2 => ZType::Nat(Arbitrary::arbitrary(g)),
-        3 => ZType::Int(Arbitrary::arbitrary(g)),
-        4 => ZType::Float(Arbitrary::arbitrary(g)),
-        5 => ZType::Text(Arbitrary::arbitrary(g)),
-        6 => ZType::Char(Arbitrary::arbitrary(g)),
-        7 => ZType::Hash(Arbitrary::arbitrary(g)),
-        _ => panic!("impossible"),
+      match BUILTINS.get(x as usize % (BUILTINS.len() + 1)) {
+        Some((_, _, typ)) => typ(Arbitrary::arbitrary(g)),
+        None => ext(g.gen_range(0x100, u64::MAX), Arbitrary::arbitrary(g)),
       }
     }
   }
@@ -212,8 +424,7 @@ mod tests {
     );
     assert_eq!(format!("{}", ZType::Bytes(Some(1))), String::from("bytes8"));
 
-    let mut parser =
-      map(preceded(tag("bytes"), parse_index), |x| ZType::Bytes(x));
+    let mut parser = map(preceded(tag("bytes"), parse_index), ZType::Bytes);
 
     assert_eq!(
       parser("bytes9"),
@@ -224,4 +435,96 @@ mod tests {
       Err(Error(ZTypeError::UnalignedTypeIndex("", 9)))
     );
   }
+
+  #[test]
+  fn ztype_registry() {
+    let mut reg = ZTypeRegistry::new();
+    let even: Validator = |dat| dat.len() % 2 == 0;
+    assert_eq!(reg.register(0x1f00, "point", Some(even)), Ok(()));
+    assert_eq!(reg.register(0x1f01, "point3d", None), Ok(()));
+    assert_eq!(
+      reg.register(0x07, "blob", None),
+      Err(ZTypeRegistryError::ReservedCode(0x07))
+    );
+    assert_eq!(
+      reg.register(0x1f00, "pt", None),
+      Err(ZTypeRegistryError::CodeTaken(0x1f00))
+    );
+    assert_eq!(
+      reg.register(0x1f02, "point", None),
+      Err(ZTypeRegistryError::NameTaken("point".to_owned()))
+    );
+    for name in &["", "Point", "vec2", "-x", "na", "nat", "extra", "s"] {
+      assert_eq!(
+        reg.register(0x1f03, name, None),
+        Err(ZTypeRegistryError::BadName(name.to_string()))
+      );
+    }
+    assert_eq!(reg.register(0x1f03, "natural", None), Ok(()));
+    assert_eq!(reg.name(0x1f01), Some("point3d"));
+    assert_eq!(reg.code("natural"), Some(0x1f03));
+
+    assert_eq!(reg.parse("point64"), Ok(("", ext(0x1f00, Some(8)))));
+    assert_eq!(reg.parse("point3d"), Ok(("", ext(0x1f01, None))));
+    assert_eq!(reg.parse("natural"), Ok(("", ext(0x1f03, None))));
+    assert_eq!(reg.parse("nat8"), Ok(("", ZType::Nat(Some(1)))));
+    assert_eq!(reg.parse("ext2a00"), Ok(("", ext(0x2a00, None))));
+    assert_eq!(reg.display(ext(0x1f00, Some(8))), "point64");
+    assert_eq!(reg.display(ext(0x2a00, Some(8))), "ext2a00_64");
+    assert_eq!(reg.display(ZType::Int(None)), "int");
+    // without the registry the names are unknown
+    assert_eq!(parse("ext1f00_64"), Ok(("", ext(0x1f00, Some(8)))));
+    assert!(parse("point").is_err());
+    assert_eq!(parse("ext1f"), Err(Error(ZTypeError::InvalidExtCode(""))));
+
+    let point = ext(0x1f00, None);
+    assert_eq!(reg.check_atom(point, &[1, 2]), Ok(()));
+    assert_eq!(
+      reg.check_atom(point, &[1, 2, 3]),
+      Err(Violation::Rejected(0x1f00))
+    );
+    assert_eq!(
+      reg.check_atom(ext(0x1f00, Some(4)), &[1, 2]),
+      Err(Violation::Length(4, 2))
+    );
+    assert_eq!(
+      reg.check_atom(ext(0x2a00, None), &[]),
+      Err(Violation::Unregistered(0x2a00))
+    );
+    assert_eq!(
+      zvalid::check_atom(point, &[1, 2]),
+      Err(Violation::Unregistered(0x1f00))
+    );
+  }
+
+  #[test]
+  fn ztype_codes() {
+    assert_eq!(ZType::Signature(None).serialize(), vec![0x0b]);
+    assert_eq!(ext(0x1f00, None).serialize(), vec![0x1f, 0x00]);
+    assert_eq!(ext(u64::MAX, None).code_len(), 8);
+    assert_eq!(
+      ZType::deserialize(&[0x1f, 0x00], Some(2)),
+      Some(ext(0x1f00, Some(2)))
+    );
+    assert_eq!(ZType::deserialize(&[0x0d], None), None);
+    assert_eq!(ExtCode::new(0xff), None);
+    assert_eq!(ExtCode::new(0x100).unwrap().as_bytes(), &[0x01, 0x00]);
+    assert_eq!(ZType::deserialize(&[0x00, 0x1f], None), None);
+  }
+
+  #[test]
+  fn ztype_table() {
+    assert_eq!(ZType::VARIANTS.len(), BUILTINS.len() + 1);
+    for (i, (name, code, typ)) in BUILTINS.iter().enumerate() {
+      let x = typ(Some(4));
+      assert_eq!(x.name(), *name);
+      assert_eq!(x.serialize(), vec![*code]);
+      assert_eq!(ZType::deserialize(&[*code], Some(4)), Some(x));
+      assert_eq!(parse(&format!("{}32", name)), Ok(("", x)));
+      assert_eq!(
+        format!("{:?}", x),
+        format!("{}(Some(4))", ZType::VARIANTS[i])
+      );
+    }
+  }
 }
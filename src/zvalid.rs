@@ -0,0 +1,283 @@
+//! Checks that the data of typed atoms means what their `ZType` says.
+//!
+//! The encoding itself accepts any bytes under any type, so `text` atoms can
+//! hold invalid UTF-8 and `float24` atoms can exist. `ZExpr::validate` walks an
+//! expression and reports every atom which breaks its type, and
+//! `ZExpr::deserialize_validated` and `parse_validated` stop at the first.
+
+use core::fmt;
+
+use crate::zlink::ZLink;
+use crate::ztype::{ZType, ZTypeRegistry};
+use crate::ZExpr;
+
+/// How an atom's data breaks its type.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Violation {
+  /// A sized type whose data is another length: (declared, actual)
+  Length(u64, usize),
+  /// Text which isn't UTF-8: (offset of the first bad byte)
+  Utf8(usize),
+  /// A char which isn't 1 to 4 bytes holding a Unicode scalar value
+  Char,
+  /// A float which isn't 2, 4, 8 or 16 bytes: (its width in bytes)
+  FloatWidth(u64),
+  /// A link which isn't the type code of a digest and then a digest of that
+  /// type's length
+  Link,
+  /// An extension type missing from the registry: (type code)
+  Unregistered(u64),
+  /// Data its extension type's validator rejects: (type code)
+  Rejected(u64),
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Self::Length(declared, actual) => {
+        write!(f, "{} bytes of data in a {} byte type", actual, declared)
+      }
+      Self::Utf8(pos) => write!(f, "invalid UTF-8 at byte {}", pos),
+      Self::Char => write!(f, "not a Unicode scalar value"),
+      Self::FloatWidth(width) => write!(f, "no {} byte float exists", width),
+      Self::Link => write!(f, "not a digest type code and digest"),
+      Self::Unregistered(code) => {
+        write!(f, "type code {:#x} isn't registered", code)
+      }
+      Self::Rejected(code) => {
+        write!(f, "rejected by the validator of type {:#x}", code)
+      }
+    }
+  }
+}
+
+/// An atom which breaks its type, with the path of cons indices leading to
+/// it from the root.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Invalid {
+  pub path: Vec<usize>,
+  pub violation: Violation,
+}
+
+impl fmt::Display for Invalid {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "atom at {:?}: {}", self.path, self.violation)
+  }
+}
+
+impl std::error::Error for Invalid {}
+
+/// Check that the data of an atom of type `typ` has the type's length, if it
+/// has one.
+pub(crate) fn check_length(typ: ZType, dat: &[u8]) -> Result<(), Violation> {
+  match typ.length() {
+    Some(len) if len != dat.len() as u64 => {
+      Err(Violation::Length(len, dat.len()))
+    }
+    _ => Ok(()),
+  }
+}
+
+/// Check the data of an atom of type `typ`. Extension types are always
+/// `Unregistered`; see `ZTypeRegistry::check_atom`.
+pub fn check_atom(typ: ZType, dat: &[u8]) -> Result<(), Violation> {
+  check_length(typ, dat)?;
+  match typ {
+    ZType::Text(_) => match std::str::from_utf8(dat) {
+      Ok(_) => Ok(()),
+      Err(e) => Err(Violation::Utf8(e.valid_up_to())),
+    },
+    ZType::Char(_) => {
+      let scalar = (1..=4).contains(&dat.len())
+        && std::char::from_u32(
+          dat.iter().fold(0u32, |n, b| (n << 8) | u32::from(*b)),
+        )
+        .is_some();
+      if scalar {
+        Ok(())
+      } else {
+        Err(Violation::Char)
+      }
+    }
+    ZType::Float(_) => match dat.len() {
+      2 | 4 | 8 | 16 => Ok(()),
+      width => Err(Violation::FloatWidth(width as u64)),
+    },
+    ZType::Link(_) => match ZLink::from_link_data(dat) {
+      Some(_) => Ok(()),
+      None => Err(Violation::Link),
+    },
+    ZType::Ext(code, _) => Err(Violation::Unregistered(code.get())),
+    _ => Ok(()),
+  }
+}
+
+impl ZExpr {
+  /// Every atom under this expression which breaks its type, in order.
+  pub fn validate(&self) -> Result<(), Vec<Invalid>> {
+    self.validate_with(&ZTypeRegistry::new())
+  }
+
+  /// Like `validate`, but with the extension types in `registry` checked by
+  /// their validators.
+  pub fn validate_with(
+    &self,
+    registry: &ZTypeRegistry,
+  ) -> Result<(), Vec<Invalid>> {
+    let invalid =
+      self.find_invalid(|typ, dat| registry.check_atom(typ, dat), false);
+    if invalid.is_empty() {
+      Ok(())
+    } else {
+      Err(invalid)
+    }
+  }
+
+  /// The first sized atom whose data isn't its type's length. `serialize`
+  /// writes such an atom as if its type had the length of its data, so it
+  /// can't be told apart from that atom once serialized.
+  pub fn check_lengths(&self) -> Result<(), Invalid> {
+    match self.find_invalid(check_length, true).pop() {
+      None => Ok(()),
+      Some(invalid) => Err(invalid),
+    }
+  }
+
+  /// The atoms which fail `check`, in order, stopping at the first if `first`.
+  fn find_invalid(
+    &self,
+    check: impl Fn(ZType, &[u8]) -> Result<(), Violation>,
+    first: bool,
+  ) -> Vec<Invalid> {
+    let mut invalid = vec![];
+    let mut path = vec![];
+    // the remaining elements of each cons being walked
+    let mut stack: Vec<std::iter::Enumerate<std::slice::Iter<ZExpr>>> = vec![];
+    let mut x = self;
+    loop {
+      match x {
+        ZExpr::Atom(typ, dat) => {
+          if let Err(violation) = check(*typ, dat) {
+            invalid.push(Invalid {
+              path: path.clone(),
+              violation,
+            });
+            if first {
+              return invalid;
+            }
+          }
+        }
+        ZExpr::Cons(xs) => stack.push(xs.iter().enumerate()),
+      }
+      x = loop {
+        let top = match stack.last_mut() {
+          Some(top) => top,
+          None => return invalid,
+        };
+        match top.next() {
+          Some((i, y)) => {
+            if path.len() == stack.len() {
+              path.pop();
+            }
+            path.push(i);
+            break y;
+          }
+          None => {
+            stack.pop();
+            path.truncate(stack.len());
+          }
+        }
+      };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::ztype::ZType::*;
+
+  fn atom(typ: ZType, dat: &[u8]) -> ZExpr {
+    ZExpr::Atom(typ, dat.to_vec())
+  }
+
+  #[test]
+  fn zvalid_atoms() {
+    assert_eq!(check_atom(Text(None), "λx".as_bytes()), Ok(()));
+    assert_eq!(check_atom(Text(None), b"ok\xff"), Err(Violation::Utf8(2)));
+    assert_eq!(check_atom(Char(Some(4)), &[0, 0, 0, 0x61]), Ok(()));
+    assert_eq!(check_atom(Char(None), &[0x03, 0xbb]), Ok(()));
+    assert_eq!(check_atom(Char(None), &[0xd8, 0x00]), Err(Violation::Char));
+    assert_eq!(check_atom(Char(None), &[0x11, 0, 0]), Err(Violation::Char));
+    assert_eq!(check_atom(Char(None), &[]), Err(Violation::Char));
+    assert_eq!(check_atom(Char(None), &[0; 5]), Err(Violation::Char));
+    assert_eq!(check_atom(Float(Some(4)), &[0; 4]), Ok(()));
+    assert_eq!(check_atom(Float(None), &[0; 16]), Ok(()));
+    assert_eq!(
+      check_atom(Float(Some(3)), &[0; 3]),
+      Err(Violation::FloatWidth(3))
+    );
+    assert_eq!(
+      check_atom(Float(None), &[0; 5]),
+      Err(Violation::FloatWidth(5))
+    );
+    assert_eq!(check_atom(Int(Some(8)), &[0; 8]), Ok(()));
+    assert_eq!(
+      check_atom(Int(Some(8)), &[0; 2]),
+      Err(Violation::Length(8, 2))
+    );
+    assert_eq!(check_atom(Int(None), &[0; 3]), Ok(()));
+    assert_eq!(check_atom(Bytes(None), b"\xff"), Ok(()));
+    let link = [&[0x07][..], &[0; 32][..]].concat();
+    assert_eq!(check_atom(Link(None), &link), Ok(()));
+    assert_eq!(check_atom(Link(Some(33)), &link), Ok(()));
+    // a sha512 code before a 32 byte digest
+    let link = [&[0x09][..], &[0; 32][..]].concat();
+    assert_eq!(check_atom(Link(None), &link), Err(Violation::Link));
+    // a code which isn't a digest type's
+    let link = [&[0x05][..], &[0; 32][..]].concat();
+    assert_eq!(check_atom(Link(None), &link), Err(Violation::Link));
+    assert_eq!(check_atom(Link(None), &[]), Err(Violation::Link));
+  }
+
+  #[test]
+  fn zvalid_paths() {
+    let good = atom(Nat(None), &[1]);
+    assert_eq!(good.validate(), Ok(()));
+    assert_eq!(
+      atom(Text(None), b"\xff").validate(),
+      Err(vec![Invalid {
+        path: vec![],
+        violation: Violation::Utf8(0)
+      }])
+    );
+    let x = ZExpr::Cons(vec![
+      good.clone(),
+      ZExpr::Cons(vec![
+        ZExpr::Cons(vec![]),
+        good.clone(),
+        atom(Float(Some(3)), &[0; 3]),
+      ]),
+      atom(Int(Some(8)), &[0]),
+      ZExpr::Cons(vec![ZExpr::Cons(vec![atom(Char(None), &[])])]),
+    ]);
+    let invalid = |path: &[usize], violation| Invalid {
+      path: path.to_vec(),
+      violation,
+    };
+    assert_eq!(
+      x.validate(),
+      Err(vec![
+        invalid(&[1, 2], Violation::FloatWidth(3)),
+        invalid(&[2], Violation::Length(8, 1)),
+        invalid(&[3, 0, 0], Violation::Char),
+      ])
+    );
+    // only the first wrong length counts for check_lengths
+    assert_eq!(
+      x.check_lengths(),
+      Err(invalid(&[2], Violation::Length(8, 1)))
+    );
+    assert_eq!(ZExpr::Cons(vec![good.clone(), good]).validate(), Ok(()));
+  }
+}